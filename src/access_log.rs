@@ -0,0 +1,272 @@
+use crate::http::{connection::Peer, request::Request};
+use jiff::{tz::TimeZone, Timestamp};
+use serde_json::json;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+#[derive(clap::ValueEnum, serde::Deserialize, Debug, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Common Log Format
+    #[default]
+    Clf,
+    /// Common Log Format followed by the referer and user-agent
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// Writes one line per response sent
+pub struct AccessLog {
+    format: LogFormat,
+    destination: Mutex<Destination>,
+    /// Set by SIGHUP, so that a rotated log file is reopened before the next write
+    reopen: Arc<AtomicBool>,
+}
+
+enum Destination {
+    Stdout,
+    File { path: Box<Path>, file: File },
+}
+
+/// What is known about a request by the time it has been answered. Unknown for requests that
+/// couldn't be parsed.
+#[derive(Default)]
+pub struct RequestInfo {
+    method: &'static str,
+    target: String,
+    version: &'static str,
+    referer: Option<Box<str>>,
+    user_agent: Option<Box<str>>,
+}
+
+impl From<&Request> for RequestInfo {
+    fn from(request: &Request) -> Self {
+        RequestInfo {
+            method: request.method().as_str(),
+            target: request.request_target(),
+            version: request.version().as_str(),
+            referer: request.header("Referer").map(Into::into),
+            user_agent: request.header("User-Agent").map(Into::into),
+        }
+    }
+}
+
+pub struct Record<'a> {
+    pub client: Option<Peer>,
+    pub request: Option<&'a RequestInfo>,
+    pub status: u16,
+    /// The length of the response body, without the head
+    pub body_bytes: u64,
+    pub duration: Duration,
+}
+
+impl AccessLog {
+    /// Logs to `path`, appending, or to stdout if `path` is `-`
+    pub fn open(path: &Path, format: LogFormat) -> io::Result<AccessLog> {
+        let destination = if path == Path::new("-") {
+            Destination::Stdout
+        } else {
+            Destination::File {
+                path: path.into(),
+                file: open_append(path)?,
+            }
+        };
+
+        let reopen = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reopen))?;
+
+        Ok(AccessLog {
+            format,
+            destination: Mutex::new(destination),
+            reopen,
+        })
+    }
+
+    pub fn log(&self, record: &Record) {
+        let line = self.format.format(record);
+
+        let mut destination = self
+            .destination
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.reopen.swap(false, Ordering::Relaxed) {
+            if let Destination::File { path, file } = &mut *destination {
+                match open_append(path) {
+                    Ok(reopened) => *file = reopened,
+                    Err(e) => log::error!("failed to reopen access log {path:?}: {e}"),
+                }
+            }
+        }
+
+        let result = match &mut *destination {
+            Destination::Stdout => writeln!(io::stdout().lock(), "{line}"),
+            Destination::File { file, .. } => writeln!(file, "{line}"),
+        };
+        if let Err(e) = result {
+            log::error!("failed to write to access log: {e}");
+        }
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl LogFormat {
+    fn format(self, record: &Record) -> String {
+        let Record {
+            client,
+            request,
+            status,
+            body_bytes,
+            duration,
+        } = record;
+        let client = client.map_or("-".to_string(), |peer| peer.host());
+        let now = Timestamp::now();
+
+        match self {
+            LogFormat::Clf | LogFormat::Combined => {
+                let time = now.to_zoned(TimeZone::UTC).strftime("%d/%b/%Y:%H:%M:%S %z");
+                let request_line = request.map_or("-".to_string(), |r| {
+                    format!("{} {} {}", r.method, escape(&r.target), r.version)
+                });
+                let bytes = match body_bytes {
+                    0 => "-".to_string(),
+                    n => n.to_string(),
+                };
+                let mut line = format!("{client} - - [{time}] \"{request_line}\" {status} {bytes}");
+                if let LogFormat::Combined = self {
+                    let quoted = |value: Option<&str>| value.map_or("-".to_string(), escape);
+                    let referer = quoted(request.and_then(|r| r.referer.as_deref()));
+                    let user_agent = quoted(request.and_then(|r| r.user_agent.as_deref()));
+                    line = format!("{line} \"{referer}\" \"{user_agent}\"");
+                }
+                line
+            }
+            LogFormat::Json => json!({
+                "time": now.to_string(),
+                "client": client,
+                "method": request.map(|r| r.method),
+                "target": request.map(|r| &r.target),
+                "version": request.map(|r| r.version),
+                "status": status,
+                "body_bytes": body_bytes,
+                "duration_ms": duration.as_secs_f64() * 1000.0,
+                "referer": request.and_then(|r| r.referer.as_deref()),
+                "user_agent": request.and_then(|r| r.user_agent.as_deref()),
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// Escapes quotes, backslashes and control characters so a value can't break the line format
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogFormat, Record, RequestInfo};
+    use crate::http::{connection::Peer, request::Request, Version};
+    use jiff::{fmt::strtime, Timestamp};
+    use std::{collections::HashMap, time::Duration};
+
+    fn request_info() -> RequestInfo {
+        let headers = [("Referer", "https://a.example/"), ("User-Agent", "curl \"8\"")];
+        let headers: HashMap<Box<str>, Box<str>> =
+            headers.iter().map(|&(key, value)| (key.into(), value.into())).collect();
+        let target = "/echo/a?b=\"c\"";
+        let request =
+            Request::from_parts("GET", target, Version::Ver1_1, headers, Box::new([]), 0).unwrap();
+        RequestInfo::from(&request)
+    }
+
+    /// Formats the record, with the time replaced by `TIME` once checked to be well-formed
+    fn format(format: LogFormat, request: Option<&RequestInfo>, body_bytes: u64) -> String {
+        let record = Record {
+            client: Some(Peer::Tcp(([192, 0, 2, 1], 4000).into())),
+            request,
+            status: 200,
+            body_bytes,
+            duration: Duration::from_micros(1500),
+        };
+        let line = format.format(&record);
+        let (before, rest) = line.split_once('[').unwrap();
+        let (time, after) = rest.split_once(']').unwrap();
+        strtime::parse("%d/%b/%Y:%H:%M:%S %z", time).unwrap();
+        assert!(time.ends_with(" +0000"), "{time}");
+        format!("{before}[TIME]{after}")
+    }
+
+    #[test]
+    fn formats_common_log_lines() {
+        let request = request_info();
+        assert_eq!(
+            format(LogFormat::Clf, Some(&request), 5),
+            r#"192.0.2.1 - - [TIME] "GET /echo/a?b=\"c\" HTTP/1.1" 200 5"#
+        );
+        assert_eq!(
+            format(LogFormat::Clf, None, 0),
+            r#"192.0.2.1 - - [TIME] "-" 200 -"#
+        );
+    }
+
+    #[test]
+    fn formats_combined_log_lines() {
+        let request = request_info();
+        assert_eq!(
+            format(LogFormat::Combined, Some(&request), 5),
+            concat!(
+                r#"192.0.2.1 - - [TIME] "GET /echo/a?b=\"c\" HTTP/1.1" 200 5 "#,
+                r#""https://a.example/" "curl \"8\"""#,
+            )
+        );
+        assert_eq!(
+            format(LogFormat::Combined, None, 0),
+            r#"192.0.2.1 - - [TIME] "-" 200 - "-" "-""#
+        );
+    }
+
+    #[test]
+    fn formats_json_log_lines() {
+        let record = Record {
+            client: Some(Peer::Unix {
+                uid: 0,
+                gid: 0,
+                pid: None,
+            }),
+            request: Some(&request_info()),
+            status: 404,
+            body_bytes: 9,
+            duration: Duration::from_micros(1500),
+        };
+        let line = LogFormat::Json.format(&record);
+        let mut json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let time = json.as_object_mut().unwrap().remove("time").unwrap();
+        time.as_str().unwrap().parse::<Timestamp>().unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "client": "unix",
+                "method": "GET",
+                "target": "/echo/a?b=\"c\"",
+                "version": "HTTP/1.1",
+                "status": 404,
+                "body_bytes": 9,
+                "duration_ms": 1.5,
+                "referer": "https://a.example/",
+                "user_agent": "curl \"8\"",
+            })
+        );
+    }
+}
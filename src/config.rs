@@ -0,0 +1,560 @@
+//! Server configuration, merged from (highest precedence first) command line flags, environment
+//! variables, a TOML file given with `--config`, and built-in defaults.
+
+use codecrafters_http_server::{
+    access_log::LogFormat,
+    health::ReadinessCheck,
+    http::{request::Limits, static_files::Mount},
+    listener,
+    tls::{self, Certificate, TlsError},
+};
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    os::fd::RawFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Parser)]
+pub struct Args {
+    /// TOML configuration file. Flags and environment variables take precedence over it.
+    #[arg(long, env = "HTTP_SERVER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Validate the configuration and exit without starting the server
+    #[arg(long)]
+    pub check_config: bool,
+    /// Address to listen on (repeatable)
+    #[arg(long, env = "HTTP_SERVER_LISTEN", value_delimiter = ',')]
+    listen: Vec<SocketAddr>,
+    /// Unix domain socket to listen on. A stale socket file left at the path is replaced.
+    #[arg(long, env = "HTTP_SERVER_UNIX_SOCKET")]
+    unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix domain socket, in octal, e.g. `660`
+    #[arg(long, env = "HTTP_SERVER_UNIX_SOCKET_MODE")]
+    unix_socket_mode: Option<String>,
+    /// Listen on this inherited file descriptor, already bound and listening (repeatable).
+    /// Sockets passed by systemd socket activation are used as well.
+    #[arg(long = "fd", env = "HTTP_SERVER_FDS", value_delimiter = ',')]
+    fds: Vec<RawFd>,
+    /// Address to listen for HTTPS on (repeatable). Requires a certificate.
+    #[arg(long, env = "HTTP_SERVER_TLS_LISTEN", value_delimiter = ',')]
+    tls_listen: Vec<SocketAddr>,
+    /// PEM certificate chain for HTTPS. More certificates, picked by SNI, can be configured in
+    /// the config file.
+    #[arg(long, env = "HTTP_SERVER_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for `--tls-cert`
+    #[arg(long, env = "HTTP_SERVER_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Number of worker threads. Defaults to one less than the available parallelism, at least 5.
+    #[arg(long, env = "HTTP_SERVER_WORKERS")]
+    workers: Option<u8>,
+    /// Close a connection once reading from it has stalled this long
+    #[arg(long, env = "HTTP_SERVER_READ_TIMEOUT_SECS")]
+    read_timeout_secs: Option<u64>,
+    /// Answer requests that haven't arrived in full after this long with 408. Defaults to 30.
+    #[arg(long, env = "HTTP_SERVER_REQUEST_TIMEOUT_SECS")]
+    request_timeout_secs: Option<u64>,
+    #[arg(long, env = "HTTP_SERVER_WRITE_TIMEOUT_SECS")]
+    write_timeout_secs: Option<u64>,
+    /// Larger request bodies are rejected with 413
+    #[arg(long, env = "HTTP_SERVER_MAX_BODY_BYTES")]
+    max_body_bytes: Option<usize>,
+    /// Larger request heads (request line and headers) are rejected with 431
+    #[arg(long, env = "HTTP_SERVER_MAX_HEADER_BYTES")]
+    max_header_bytes: Option<usize>,
+    /// Don't compress responses. `--no-compression=false` compresses them even if the file
+    /// says not to.
+    #[arg(
+        long,
+        env = "HTTP_SERVER_NO_COMPRESSION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    no_compression: Option<bool>,
+    /// Smaller response bodies aren't compressed
+    #[arg(long, env = "HTTP_SERVER_COMPRESSION_MIN_BYTES")]
+    compression_min_bytes: Option<usize>,
+    /// Serve and store files under `/files/` from this directory
+    #[arg(long, env = "HTTP_SERVER_DIRECTORY")]
+    directory: Option<Box<Path>>,
+    /// Directory of error page templates, named by status code (e.g. `404.html` or `5xx.html`)
+    #[arg(long, env = "HTTP_SERVER_ERROR_PAGES")]
+    error_pages: Option<Box<Path>>,
+    /// Allow cross-origin requests from this origin (repeatable). `*` allows any origin.
+    #[arg(long, env = "HTTP_SERVER_CORS_ORIGIN", value_delimiter = ',')]
+    cors_origin: Vec<Box<str>>,
+    /// e.g. `info`, `debug`. `RUST_LOG` can refine it per module.
+    #[arg(long, env = "HTTP_SERVER_LOG_LEVEL")]
+    log_level: Option<LevelFilter>,
+    /// File to append an access log line to for every response. `-` logs to stdout.
+    #[arg(long, env = "HTTP_SERVER_ACCESS_LOG")]
+    access_log: Option<Box<Path>>,
+    #[arg(long, env = "HTTP_SERVER_ACCESS_LOG_FORMAT", value_enum)]
+    access_log_format: Option<LogFormat>,
+    /// Serve Prometheus metrics at this path, e.g. `/metrics`
+    #[arg(long, env = "HTTP_SERVER_METRICS_PATH")]
+    metrics_path: Option<Box<str>>,
+    /// Serve metrics from a separate listener on this address instead of the main one
+    #[arg(long, env = "HTTP_SERVER_METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
+    #[arg(long, env = "HTTP_SERVER_LIVENESS_PATH")]
+    liveness_path: Option<Box<str>>,
+    #[arg(long, env = "HTTP_SERVER_READINESS_PATH")]
+    readiness_path: Option<Box<str>>,
+    /// Conditions for readiness (comma separated). Defaults to all of them.
+    #[arg(long, env = "HTTP_SERVER_READINESS_CHECKS", value_enum, value_delimiter = ',')]
+    readiness_checks: Vec<ReadinessCheck>,
+    /// Readiness fails once more connections than this are waiting for a worker. Defaults to the
+    /// number of workers.
+    #[arg(long, env = "HTTP_SERVER_MAX_QUEUE_DEPTH")]
+    max_queue_depth: Option<usize>,
+    /// After SIGTERM or SIGINT, keep serving (while failing readiness) this many seconds before
+    /// exiting. A second signal exits immediately.
+    #[arg(long, env = "HTTP_SERVER_DRAIN_SECS")]
+    drain_secs: Option<u64>,
+    /// Hide error details from responses. `--production=false` shows them even if the file says
+    /// not to.
+    #[arg(
+        long,
+        env = "HTTP_SERVER_PRODUCTION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    production: Option<bool>,
+}
+
+/// The layout of the `--config` file. Every field is optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerSection,
+    tls: TlsSection,
+    timeouts: TimeoutsSection,
+    limits: LimitsSection,
+    compression: CompressionSection,
+    #[serde(rename = "static")]
+    static_files: StaticSection,
+    logging: LoggingSection,
+    metrics: MetricsSection,
+    health: HealthSection,
+    cors: CorsSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    listen: Vec<SocketAddr>,
+    unix_socket: Option<PathBuf>,
+    unix_socket_mode: Option<String>,
+    fds: Vec<RawFd>,
+    workers: Option<u8>,
+    production: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    listen: Vec<SocketAddr>,
+    certificates: Vec<Certificate>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TimeoutsSection {
+    read_secs: Option<u64>,
+    request_secs: Option<u64>,
+    write_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSection {
+    max_body_bytes: Option<usize>,
+    max_header_bytes: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct CompressionSection {
+    enabled: Option<bool>,
+    min_bytes: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct StaticSection {
+    /// Shorthand for a writable mount at `/files/`
+    directory: Option<Box<Path>>,
+    mounts: Vec<Mount>,
+    error_pages: Option<Box<Path>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    level: Option<String>,
+    access_log: Option<Box<Path>>,
+    access_log_format: Option<LogFormat>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct MetricsSection {
+    path: Option<Box<str>>,
+    listen: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct HealthSection {
+    liveness_path: Option<Box<str>>,
+    readiness_path: Option<Box<str>>,
+    readiness_checks: Option<Vec<ReadinessCheck>>,
+    max_queue_depth: Option<usize>,
+    drain_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct CorsSection {
+    origins: Vec<Box<str>>,
+}
+
+/// The final configuration, after merging every source
+pub struct Config {
+    /// Defaults to `127.0.0.1:4221` when no other listener is configured either
+    pub listen: Vec<SocketAddr>,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_mode: Option<u32>,
+    /// Given with `--fd` or passed by systemd
+    pub fds: Vec<RawFd>,
+    pub tls_listen: Vec<SocketAddr>,
+    /// The first one is the default, for clients not asking for a name another one has
+    pub tls_certificates: Vec<Certificate>,
+    /// `None` sizes the pool automatically
+    pub workers: Option<u8>,
+    pub read_timeout: Option<Duration>,
+    pub request_timeout: Duration,
+    pub write_timeout: Option<Duration>,
+    pub limits: Limits,
+    /// `None` disables compression, otherwise the minimum body size to compress
+    pub compression_min_bytes: Option<usize>,
+    pub mounts: Vec<Mount>,
+    pub error_pages: Option<Box<Path>>,
+    pub cors_origins: Vec<Box<str>>,
+    pub log_level: LevelFilter,
+    pub access_log: Option<Box<Path>>,
+    pub access_log_format: LogFormat,
+    pub metrics_path: Option<Box<str>>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub liveness_path: Box<str>,
+    pub readiness_path: Box<str>,
+    pub readiness_checks: Vec<ReadinessCheck>,
+    pub max_queue_depth: Option<usize>,
+    pub drain_secs: u64,
+    pub production: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read {path:?}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid configuration file {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+    #[error("invalid value for {key}: {reason}")]
+    Invalid { key: &'static str, reason: String },
+}
+
+impl Config {
+    pub fn load(args: Args) -> Result<Config, ConfigError> {
+        let file = match &args.config {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+                    path: path.clone(),
+                    source,
+                })?;
+                toml::from_str(&text).map_err(|source| ConfigError::Parse {
+                    path: path.clone(),
+                    source,
+                })?
+            }
+            None => FileConfig::default(),
+        };
+        Config::merge(args, file)
+    }
+
+    fn merge(args: Args, file: FileConfig) -> Result<Config, ConfigError> {
+        let FileConfig {
+            server,
+            tls: tls_section,
+            timeouts,
+            limits,
+            compression,
+            static_files,
+            logging,
+            metrics,
+            health,
+            cors,
+        } = file;
+
+        let file_log_level = logging
+            .level
+            .map(|level| {
+                LevelFilter::from_str(&level).map_err(|e| ConfigError::Invalid {
+                    key: "logging.level",
+                    reason: e.to_string(),
+                })
+            })
+            .transpose()?;
+
+        let unix_socket_mode = args
+            .unix_socket_mode
+            .or(server.unix_socket_mode)
+            .map(|mode| {
+                u32::from_str_radix(&mode, 8)
+                    .ok()
+                    .filter(|&mode| mode <= 0o777)
+                    .ok_or_else(|| invalid("server.unix_socket_mode", "must be octal, e.g. 660"))
+            })
+            .transpose()?;
+
+        let compression_enabled = args
+            .no_compression
+            .map(|disabled| !disabled)
+            .or(compression.enabled)
+            .unwrap_or(true);
+        let default_limits = Limits::default();
+
+        let mut config = Config {
+            listen: non_empty_or(args.listen, server.listen).unwrap_or_default(),
+            unix_socket: args.unix_socket.or(server.unix_socket),
+            unix_socket_mode,
+            fds: inherited_fds(non_empty_or(args.fds, server.fds).unwrap_or_default()),
+            tls_listen: non_empty_or(args.tls_listen, tls_section.listen).unwrap_or_default(),
+            tls_certificates: match (args.tls_cert, args.tls_key) {
+                (Some(cert), Some(key)) => vec![Certificate {
+                    cert,
+                    key,
+                    hostnames: Vec::new(),
+                }],
+                _ => tls_section.certificates,
+            },
+            workers: args.workers.or(server.workers),
+            read_timeout: args
+                .read_timeout_secs
+                .or(timeouts.read_secs)
+                .map(Duration::from_secs),
+            request_timeout: Duration::from_secs(
+                args.request_timeout_secs
+                    .or(timeouts.request_secs)
+                    .unwrap_or(30),
+            ),
+            write_timeout: args
+                .write_timeout_secs
+                .or(timeouts.write_secs)
+                .map(Duration::from_secs),
+            limits: Limits {
+                max_body_bytes: args
+                    .max_body_bytes
+                    .or(limits.max_body_bytes)
+                    .unwrap_or(default_limits.max_body_bytes),
+                max_header_bytes: args
+                    .max_header_bytes
+                    .or(limits.max_header_bytes)
+                    .unwrap_or(default_limits.max_header_bytes),
+            },
+            compression_min_bytes: compression_enabled.then(|| {
+                args.compression_min_bytes
+                    .or(compression.min_bytes)
+                    .unwrap_or(0)
+            }),
+            mounts: args
+                .directory
+                .or(static_files.directory)
+                .map(|dir| Mount::writable("/files/", dir))
+                .into_iter()
+                .chain(static_files.mounts)
+                .collect(),
+            error_pages: args.error_pages.or(static_files.error_pages),
+            cors_origins: non_empty_or(args.cors_origin, cors.origins).unwrap_or_default(),
+            log_level: args
+                .log_level
+                .or(file_log_level)
+                .unwrap_or(LevelFilter::Debug),
+            access_log: args.access_log.or(logging.access_log),
+            access_log_format: args
+                .access_log_format
+                .or(logging.access_log_format)
+                .unwrap_or_default(),
+            metrics_path: args.metrics_path.or(metrics.path),
+            metrics_listen: args.metrics_listen.or(metrics.listen),
+            liveness_path: args
+                .liveness_path
+                .or(health.liveness_path)
+                .unwrap_or_else(|| "/healthz".into()),
+            readiness_path: args
+                .readiness_path
+                .or(health.readiness_path)
+                .unwrap_or_else(|| "/readyz".into()),
+            readiness_checks: non_empty_or(args.readiness_checks, health.readiness_checks)
+                .unwrap_or_else(|| {
+                    vec![
+                        ReadinessCheck::Draining,
+                        ReadinessCheck::Queue,
+                        ReadinessCheck::Directory,
+                    ]
+                }),
+            max_queue_depth: args.max_queue_depth.or(health.max_queue_depth),
+            drain_secs: args.drain_secs.or(health.drain_secs).unwrap_or(10),
+            production: args.production.or(server.production).unwrap_or(false),
+        };
+        let has_listener = !config.listen.is_empty()
+            || !config.tls_listen.is_empty()
+            || config.unix_socket.is_some()
+            || !config.fds.is_empty();
+        if !has_listener {
+            config.listen.push(SocketAddr::from(([127, 0, 0, 1], 4221)));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let paths = [
+            ("health.liveness_path", Some(&self.liveness_path)),
+            ("health.readiness_path", Some(&self.readiness_path)),
+            ("metrics.path", self.metrics_path.as_ref()),
+        ];
+        for (key, path) in paths {
+            if path.is_some_and(|path| !path.starts_with('/')) {
+                return Err(invalid(key, "must start with '/'"));
+            }
+        }
+        for mount in &self.mounts {
+            if !mount.prefix.starts_with('/') {
+                return Err(invalid("static.mounts.prefix", "must start with '/'"));
+            }
+            if !mount.root.is_dir() {
+                return Err(ConfigError::Invalid {
+                    key: "static.mounts.root",
+                    reason: format!("{:?} is not a directory", mount.root),
+                });
+            }
+        }
+        if !self.tls_listen.is_empty() {
+            tls::check(&self.tls_certificates)?;
+        }
+        if self.workers == Some(0) {
+            return Err(invalid("server.workers", "must be at least 1"));
+        }
+        let timeouts = [
+            ("timeouts.read_secs", self.read_timeout),
+            ("timeouts.request_secs", Some(self.request_timeout)),
+            ("timeouts.write_secs", self.write_timeout),
+        ];
+        for (key, timeout) in timeouts {
+            if timeout == Some(Duration::ZERO) {
+                return Err(invalid(key, "must be at least 1"));
+            }
+        }
+        if self.error_pages.as_ref().is_some_and(|dir| !dir.is_dir()) {
+            return Err(invalid("static.error_pages", "is not a directory"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(key: &'static str, reason: &str) -> ConfigError {
+    ConfigError::Invalid {
+        key,
+        reason: reason.to_string(),
+    }
+}
+
+/// `fds` plus those passed by systemd, each once: every one becomes owned by a listener
+fn inherited_fds(mut fds: Vec<RawFd>) -> Vec<RawFd> {
+    fds.extend(listener::systemd_fds());
+    fds.sort_unstable();
+    fds.dedup();
+    fds
+}
+
+/// Lists given on the command line replace those from the file rather than extending them
+fn non_empty_or<T>(from_args: Vec<T>, from_file: impl Into<Option<Vec<T>>>) -> Option<Vec<T>> {
+    let from_file = from_file.into().filter(|v| !v.is_empty());
+    Some(from_args).filter(|v| !v.is_empty()).or(from_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Args, Config, FileConfig};
+    use clap::Parser;
+    use std::{
+        env,
+        sync::{Mutex, PoisonError},
+        time::Duration,
+    };
+
+    /// Every parse reads the environment, so tests setting it take turns
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Merges the config file `file`, the environment variables `vars` and the flags `flags`
+    fn merge(file: &str, vars: &[(&str, &str)], flags: &[&str]) -> Config {
+        let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, value) in vars {
+            env::set_var(key, value);
+        }
+        let args = Args::try_parse_from(["http-server"].iter().chain(flags));
+        for (key, _) in vars {
+            env::remove_var(key);
+        }
+        let file: FileConfig = toml::from_str(file).expect("valid config file");
+        Config::merge(args.expect("valid flags"), file).expect("valid config")
+    }
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let file = "[timeouts]\nread_secs = 1";
+        let env = [("HTTP_SERVER_READ_TIMEOUT_SECS", "2")];
+        let read_timeout = |config: Config| config.read_timeout.map(|t| t.as_secs());
+        assert_eq!(read_timeout(merge("", &[], &[])), None);
+        assert_eq!(read_timeout(merge(file, &[], &[])), Some(1));
+        assert_eq!(read_timeout(merge(file, &env, &[])), Some(2));
+        let flags = ["--read-timeout-secs", "3"];
+        assert_eq!(read_timeout(merge(file, &env, &flags)), Some(3));
+        assert_eq!(merge("", &[], &[]).request_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn booleans_can_be_overridden_either_way() {
+        let file = "[server]\nproduction = true";
+        let env = [("HTTP_SERVER_PRODUCTION", "false")];
+        assert!(!merge("", &[], &[]).production);
+        assert!(merge(file, &[], &[]).production);
+        assert!(!merge(file, &env, &[]).production);
+        assert!(merge(file, &env, &["--production"]).production);
+        assert!(!merge(file, &[], &["--production=false"]).production);
+
+        let file = "[compression]\nenabled = false";
+        let env = [("HTTP_SERVER_NO_COMPRESSION", "false")];
+        assert_eq!(merge("", &[], &[]).compression_min_bytes, Some(0));
+        assert_eq!(merge(file, &[], &[]).compression_min_bytes, None);
+        assert_eq!(merge(file, &env, &[]).compression_min_bytes, Some(0));
+        let flags = ["--no-compression"];
+        assert_eq!(merge(file, &env, &flags).compression_min_bytes, None);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    http::{
+        middleware::{Middleware, Next},
+        request::Request,
+        response::{success, Response, ResponseStatus},
+        Method,
+    },
+    thread_pool::PoolStats,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// A condition the server must meet to be ready for traffic
+#[derive(clap::ValueEnum, serde::Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessCheck {
+    /// Not shutting down
+    Draining,
+    /// The thread pool's queue isn't saturated
+    Queue,
+    /// Every writable static mount can be written to
+    Directory,
+}
+
+impl ReadinessCheck {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Draining => "draining",
+            Self::Queue => "queue",
+            Self::Directory => "directory",
+        }
+    }
+}
+
+/// Answers liveness probes on `liveness_path` and readiness probes on `readiness_path`
+pub struct HealthEndpoints {
+    pub liveness_path: Box<str>,
+    pub readiness_path: Box<str>,
+    pub checks: Vec<ReadinessCheck>,
+    /// Readiness fails once more jobs than this are waiting for a worker
+    pub max_queue_depth: usize,
+    pub draining: Arc<AtomicBool>,
+    pub pool: Arc<PoolStats>,
+    /// Roots of the writable static mounts
+    pub writable_roots: Vec<Box<Path>>,
+}
+
+/// Whether files may be created in `dir`, asked of the kernel rather than found out by creating
+/// one, so that probes don't touch the disk
+fn writable(dir: &Path) -> io::Result<()> {
+    if !fs::metadata(dir)?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory"));
+    }
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: `path` is NUL-terminated and outlives the call
+    match unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+impl HealthEndpoints {
+    fn run_check(&self, check: ReadinessCheck) -> Result<(), String> {
+        match check {
+            ReadinessCheck::Draining if self.draining.load(Ordering::Relaxed) => {
+                Err("shutting down".to_string())
+            }
+            ReadinessCheck::Queue if self.pool.queued() > self.max_queue_depth => {
+                Err(format!("{} jobs queued", self.pool.queued()))
+            }
+            ReadinessCheck::Draining | ReadinessCheck::Queue => Ok(()),
+            ReadinessCheck::Directory => self.writable_roots.iter().try_for_each(|dir| {
+                writable(dir).map_err(|e| format!("{dir:?} is not writable: {e}"))
+            }),
+        }
+    }
+
+    fn readiness(&self) -> Response {
+        let mut ready = true;
+        let mut results = Map::new();
+        for &check in &self.checks {
+            let result = match self.run_check(check) {
+                Ok(()) => "ok".to_string(),
+                Err(reason) => {
+                    log::warn!("readiness check {} failed: {reason}", check.name());
+                    ready = false;
+                    format!("failing: {reason}")
+                }
+            };
+            results.insert(check.name().to_string(), Value::String(result));
+        }
+
+        let status = if ready { "ready" } else { "not ready" };
+        let mut response = success::json(&json!({ "status": status, "checks": results }));
+        if !ready {
+            response.set_status(ResponseStatus::ServiceUnavailable);
+        }
+        response
+    }
+}
+
+impl Middleware for HealthEndpoints {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        if *request.method() != Method::Get {
+            return next.run(request);
+        }
+        let target = request.target();
+        if target == *self.liveness_path {
+            success::json(&json!({ "status": "alive" }))
+        } else if target == *self.readiness_path {
+            self.readiness()
+        } else {
+            next.run(request)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HealthEndpoints, ReadinessCheck};
+    use crate::{
+        http::{
+            middleware::Chain,
+            request::Request,
+            response::client_error,
+            Version,
+        },
+        thread_pool::{PoolStats, ThreadPool},
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        fs,
+        sync::{atomic::AtomicBool, mpsc, Arc},
+    };
+
+    fn endpoints(checks: &[ReadinessCheck], pool: Arc<PoolStats>) -> HealthEndpoints {
+        HealthEndpoints {
+            liveness_path: "/healthz".into(),
+            readiness_path: "/readyz".into(),
+            checks: checks.to_vec(),
+            max_queue_depth: 1,
+            draining: Arc::default(),
+            pool,
+            writable_roots: Vec::new(),
+        }
+    }
+
+    /// The status and JSON body of the answer to `GET target`
+    fn probe(health: HealthEndpoints, target: &str) -> (u16, Value) {
+        let request =
+            Request::from_parts("GET", target, Version::Ver1_1, HashMap::new(), Box::new([]), 0);
+        let chain = Chain::default().with(health);
+        let response = chain.handle(request.unwrap(), &|_| client_error::not_found());
+        let (status, _, body) = response.into_parts();
+        (status.code(), serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[test]
+    fn answers_liveness_probes() {
+        let mut health = endpoints(&[ReadinessCheck::Draining], Arc::default());
+        health.draining = Arc::new(AtomicBool::new(true));
+        assert_eq!(probe(health, "/healthz"), (200, json!({ "status": "alive" })));
+        let health = endpoints(&[], Arc::default());
+        assert_eq!(probe(health, "/other").0, 404);
+    }
+
+    #[test]
+    fn fails_readiness_while_draining() {
+        let health = endpoints(&[ReadinessCheck::Draining], Arc::default());
+        let expected = json!({ "status": "ready", "checks": { "draining": "ok" } });
+        assert_eq!(probe(health, "/readyz"), (200, expected));
+
+        let mut health = endpoints(&[ReadinessCheck::Draining], Arc::default());
+        health.draining = Arc::new(AtomicBool::new(true));
+        let expected = json!({
+            "status": "not ready",
+            "checks": { "draining": "failing: shutting down" },
+        });
+        assert_eq!(probe(health, "/readyz"), (503, expected));
+    }
+
+    #[test]
+    fn fails_readiness_while_the_queue_is_saturated() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = blocked.recv();
+        });
+        // the worker can't take these while it's blocked
+        pool.execute(|| {});
+        pool.execute(|| {});
+        while pool.stats().queued() > 2 {
+            std::thread::yield_now();
+        }
+
+        let (status, body) = probe(endpoints(&[ReadinessCheck::Queue], pool.stats()), "/readyz");
+        assert_eq!(status, 503);
+        assert_eq!(body["checks"]["queue"], "failing: 2 jobs queued");
+
+        release.send(()).unwrap();
+        while pool.stats().queued() > 0 {
+            std::thread::yield_now();
+        }
+        let (status, body) = probe(endpoints(&[ReadinessCheck::Queue], pool.stats()), "/readyz");
+        assert_eq!(status, 200);
+        assert_eq!(body["checks"]["queue"], "ok");
+    }
+
+    #[test]
+    fn fails_readiness_without_writable_directories() {
+        let dir = std::env::temp_dir().join(format!("health-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+
+        let mut health = endpoints(&[ReadinessCheck::Directory], Arc::default());
+        health.writable_roots = vec![dir.clone().into()];
+        assert_eq!(probe(health, "/readyz").0, 200);
+
+        for root in [dir.join("file"), dir.join("missing")] {
+            let mut health = endpoints(&[ReadinessCheck::Directory], Arc::default());
+            health.writable_roots = vec![dir.clone().into(), root.clone().into()];
+            let (status, body) = probe(health, "/readyz");
+            assert_eq!(status, 503);
+            let reason = body["checks"]["directory"].as_str().unwrap();
+            let expected = format!("failing: {root:?} is not writable");
+            assert!(reason.starts_with(&expected), "{reason}");
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::http::{error::BadRequest, media_type::MediaType};
+
+/// A type that can be parsed out of a request body, guided by the request's `Content-Type`
+pub trait FromBody: Sized {
+    /// Whether a body of this media type (e.g. `"application/json"`) can be parsed as `Self`
+    fn accepts(essence: &str) -> bool;
+
+    fn parse(body: &[u8]) -> Result<Self, BadRequest>;
+
+    /// # Errors
+    /// Errors with [`BadRequest::UnsupportedMediaType`] if the `Content-Type` doesn't match, or
+    /// with the specific cause if the body is malformed
+    fn from_body(content_type: Option<&str>, body: &[u8]) -> Result<Self, BadRequest> {
+        let content_type = content_type.ok_or(BadRequest::MissingHeader("Content-Type"))?;
+        let media_type = MediaType::parse(content_type);
+        if !Self::accepts(media_type.essence()) {
+            return Err(BadRequest::UnsupportedMediaType(media_type.essence().into()));
+        }
+        Self::parse(body)
+    }
+}
+
+/// An `application/x-www-form-urlencoded` body. Keys may repeat; order is preserved.
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(Box<str>, Box<str>)>,
+}
+
+impl Form {
+    /// The first value given for `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.as_ref() == key)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(k, _)| k.as_ref() == key)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_ref(), v.as_ref()))
+    }
+}
+
+impl FromBody for Form {
+    fn accepts(essence: &str) -> bool {
+        essence == "application/x-www-form-urlencoded"
+    }
+
+    fn parse(body: &[u8]) -> Result<Self, BadRequest> {
+        let fields = body
+            .split(|b| *b == b'&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = match pair.iter().position(|b| *b == b'=') {
+                    Some(pos) => (&pair[..pos], &pair[pos + 1..]),
+                    None => (pair, &[][..]),
+                };
+                Ok((url_decode(key)?, url_decode(value)?))
+            })
+            .collect::<Result<_, BadRequest>>()?;
+        Ok(Form { fields })
+    }
+}
+
+/// An `application/json` (or `+json` suffixed) body
+#[derive(Debug)]
+pub struct Json(pub serde_json::Value);
+
+impl FromBody for Json {
+    fn accepts(essence: &str) -> bool {
+        essence == "application/json" || essence.ends_with("+json")
+    }
+
+    fn parse(body: &[u8]) -> Result<Self, BadRequest> {
+        serde_json::from_slice(body)
+            .map(Json)
+            .map_err(|e| BadRequest::MalformedJson(e.to_string()))
+    }
+}
+
+/// Decodes `+` as a space and `%XX` escapes
+fn url_decode(bytes: &[u8]) -> Result<Box<str>, BadRequest> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        match b {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex_digit = |b: Option<&u8>| b.and_then(|b| char::from(*b).to_digit(16));
+                let (Some(high), Some(low)) = (hex_digit(iter.next()), hex_digit(iter.next()))
+                else {
+                    return Err(BadRequest::MalformedForm);
+                };
+                decoded.push((high << 4 | low) as u8);
+            }
+            other => decoded.push(other),
+        }
+    }
+    Ok(String::from_utf8(decoded)?.into_boxed_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url_decode, Form, FromBody, Json};
+    use crate::http::error::BadRequest;
+
+    #[test]
+    fn decodes_escapes_and_pluses() {
+        assert_eq!(&*url_decode(b"a+b%20c%2Bd%e2%82%AC").unwrap(), "a b c+d\u{20ac}");
+        assert_eq!(&*url_decode(b"").unwrap(), "");
+    }
+
+    #[test]
+    fn rejects_malformed_escapes() {
+        for malformed in [&b"%G1"[..], b"%1G", b"a%", b"a%2"] {
+            let decoded = url_decode(malformed);
+            assert!(matches!(decoded, Err(BadRequest::MalformedForm)), "{decoded:?}");
+        }
+        assert!(matches!(url_decode(b"%ff"), Err(BadRequest::NotUTF8)));
+    }
+
+    #[test]
+    fn parses_forms_by_content_type() {
+        let content_type = Some("application/x-www-form-urlencoded; charset=utf-8");
+        let form = Form::from_body(content_type, b"a=1&&b&a=x+y").unwrap();
+        assert_eq!(form.get_all("a").collect::<Vec<_>>(), ["1", "x y"]);
+        assert_eq!(form.get("b"), Some(""));
+        assert!(matches!(Form::from_body(content_type, b"a=%"), Err(BadRequest::MalformedForm)));
+    }
+
+    #[test]
+    fn parses_json_by_content_type() {
+        for content_type in ["application/json", "application/problem+json"] {
+            let Json(value) = Json::from_body(Some(content_type), br#"{"a":1}"#).unwrap();
+            assert_eq!(value["a"], 1);
+        }
+        let malformed = Json::from_body(Some("application/json"), b"{");
+        assert!(matches!(malformed, Err(BadRequest::MalformedJson(_))));
+    }
+
+    #[test]
+    fn rejects_a_mismatched_or_missing_content_type() {
+        let form = Form::from_body(Some("application/json"), b"a=1");
+        let Err(BadRequest::UnsupportedMediaType(essence)) = form else {
+            panic!("{form:?}");
+        };
+        assert_eq!(&*essence, "application/json");
+        let json = Json::from_body(Some("application/x-www-form-urlencoded"), b"{}");
+        assert!(matches!(json, Err(BadRequest::UnsupportedMediaType(_))));
+        let json = Json::from_body(None, b"{}");
+        assert!(matches!(json, Err(BadRequest::MissingHeader("Content-Type"))));
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    cell::RefCell,
+    fmt::{self, Display, Formatter},
+    io::{self, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    os::unix::net::UnixStream,
+    rc::Rc,
+};
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// A local process, identified by its credentials where the OS provides them
+    Unix { uid: u32, gid: u32, pid: Option<i32> },
+}
+
+impl Peer {
+    /// The credentials of the process connected through `stream`
+    pub fn of_unix(stream: &UnixStream) -> io::Result<Peer> {
+        peer_credentials(stream)
+    }
+
+    /// The remote host for logs: an IP address, or `unix`
+    pub fn host(&self) -> String {
+        match self {
+            Peer::Tcp(addr) => addr.ip().to_string(),
+            Peer::Unix { .. } => "unix".to_string(),
+        }
+    }
+}
+
+impl Display for Peer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{addr}"),
+            Peer::Unix { uid, pid: Some(pid), .. } => write!(f, "unix:uid={uid},pid={pid}"),
+            Peer::Unix { uid, pid: None, .. } => write!(f, "unix:uid={uid}"),
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_credentials(stream: &UnixStream) -> io::Result<Peer> {
+    use std::os::fd::AsRawFd;
+
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `credentials` and `len` describe a writable `ucred`, as SO_PEERCRED expects
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut credentials as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Peer::Unix {
+        uid: credentials.uid,
+        gid: credentials.gid,
+        pid: Some(credentials.pid),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_credentials(stream: &UnixStream) -> io::Result<Peer> {
+    use std::os::fd::AsRawFd;
+
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: `uid` and `gid` are writable, as getpeereid expects
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Peer::Unix {
+        uid,
+        gid,
+        pid: None,
+    })
+}
+
+/// Both directions of a transport: requests are read from `reader`, responses written to
+/// `writer`. The reader is buffered for the life of the connection, so that bytes of a pipelined
+/// request read along with the previous one aren't lost.
+pub struct Connection<R, W> {
+    pub(crate) reader: BufReader<R>,
+    pub(crate) writer: W,
+}
+
+impl<R: Read, W: Write> Connection<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Connection {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    pub fn into_parts(self) -> (BufReader<R>, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl Connection<TcpStream, TcpStream> {
+    pub fn tcp(stream: TcpStream) -> io::Result<Self> {
+        Ok(Connection::new(stream.try_clone()?, stream))
+    }
+}
+
+impl Connection<UnixStream, UnixStream> {
+    pub fn unix(stream: UnixStream) -> io::Result<Self> {
+        Ok(Connection::new(stream.try_clone()?, stream))
+    }
+}
+
+impl<S: Read + Write> Connection<Shared<S>, Shared<S>> {
+    /// For streams that can't be split in two, such as TLS sessions
+    pub fn shared(stream: S) -> Self {
+        let shared = Shared(Rc::new(RefCell::new(stream)));
+        Connection::new(shared.clone(), shared)
+    }
+}
+
+impl<S> Connection<Shared<S>, Shared<S>> {
+    /// Takes the stream back, along with what the reader buffered from it but didn't hand out
+    pub fn into_inner(self) -> (S, Vec<u8>) {
+        let unread = self.reader.buffer().to_vec();
+        drop(self.reader);
+        let Ok(stream) = Rc::try_unwrap(self.writer.0) else {
+            unreachable!("the reader held the only other reference");
+        };
+        (stream.into_inner(), unread)
+    }
+}
+
+/// One stream used as both the reader and the writer of a `Connection`
+pub struct Shared<S>(Rc<RefCell<S>>);
+
+impl<S> Clone for Shared<S> {
+    fn clone(&self) -> Self {
+        Shared(Rc::clone(&self.0))
+    }
+}
+
+impl<S: Read> Read for Shared<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl<S: Write> Write for Shared<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Connection;
+    use crate::http::{
+        request::{AppState, Limits, Parser, RequestSource},
+        HTTPCarrier,
+    };
+    use std::io::Cursor;
+
+    /// Answers every request in `input` and returns everything written back
+    fn exchange(input: &[u8]) -> String {
+        let mut connection = Connection::new(Cursor::new(input), Vec::new());
+        let state = AppState::default();
+        loop {
+            let response = match connection.read_request(&Limits::default()) {
+                Ok(request) => request.handle(&state),
+                Err(Some(response)) => response,
+                Err(None) => break,
+            };
+            let closing = response.closing();
+            connection.respond(response).unwrap();
+            if closing {
+                break;
+            }
+        }
+        String::from_utf8(connection.into_parts().1).unwrap()
+    }
+
+    #[test]
+    fn echoes_the_path() {
+        assert_eq!(
+            exchange(b"GET /echo/abc HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nVary: Accept\r\n\
+            Content-Type: text/plain\r\nContent-Length: 3\r\n\r\nabc"
+        );
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let response = exchange(
+            b"GET /echo/one HTTP/1.1\r\n\r\n\
+            POST /echo HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 5\r\n\r\na=b&c\
+            GET /echo/two HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nVary: Accept\r\n\
+            Content-Type: text/plain\r\nContent-Length: 3\r\n\r\none\
+            HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 7\r\n\r\na=b\nc=\n\
+            HTTP/1.1 200 OK\r\nVary: Accept\r\n\
+            Content-Type: text/plain\r\nContent-Length: 3\r\n\r\ntwo"
+        );
+    }
+
+    #[test]
+    fn parses_a_head_that_arrives_byte_by_byte() {
+        let input = b"GET /echo/abc HTTP/1.1\r\nHost: localhost\r\n\r\nGET /";
+        let head_len = input.len() - b"GET /".len();
+        let limits = Limits::default();
+        let mut parser = Parser::default();
+        for end in 0..head_len {
+            let parsed = parser.parse_head(&input[..end], &limits);
+            assert!(matches!(parsed, Ok(None)), "parsed a head from {end} bytes");
+        }
+        let Ok(Some((head, len))) = parser.parse_head(input, &limits) else {
+            panic!("failed to parse the complete head");
+        };
+        assert_eq!(len, head_len);
+        assert_eq!(head.request().target(), "/echo/abc");
+    }
+
+    #[test]
+    fn rejects_empty_and_non_ascii_targets() {
+        let limits = Limits::default();
+        for input in ["GET  HTTP/1.1\r\n\r\n", "GET \u{e9}/ HTTP/1.1\r\n\r\n"] {
+            let Err(Some(response)) = Parser::default().parse_head(input.as_bytes(), &limits) else {
+                panic!("accepted {input:?}");
+            };
+            assert_eq!(response.status().code(), 400, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn reads_asterisk_and_absolute_form_targets() {
+        let target = |input: &str| {
+            let parsed = Parser::default().parse_head(input.as_bytes(), &Limits::default());
+            let Ok(Some((head, _))) = parsed else {
+                panic!("failed to parse {input:?}");
+            };
+            head.request().request_target()
+        };
+        assert_eq!(target("OPTIONS * HTTP/1.1\r\n\r\n"), "*");
+        assert_eq!(target("GET http://localhost/echo/a?b HTTP/1.1\r\n\r\n"), "/echo/a?b");
+        assert_eq!(target("GET HTTPS://localhost HTTP/1.1\r\n\r\n"), "/");
+    }
+
+    #[test]
+    fn rejects_a_missing_content_length() {
+        let response = exchange(b"POST /echo HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+    }
+
+    #[test]
+    fn rejects_posts_to_unknown_targets() {
+        let response = exchange(b"POST /nope HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{response}");
+    }
+
+    #[test]
+    fn keeps_alive_by_default_only_from_http_1_1() {
+        let keep_alive = |input: &[u8]| {
+            let mut connection = Connection::new(Cursor::new(input), Vec::new());
+            let Ok(request) = connection.read_request(&Limits::default()) else {
+                panic!("failed to parse {input:?}");
+            };
+            request.keep_alive()
+        };
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"));
+        assert!(keep_alive(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nConnection: TE, close\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_oversized_bodies_and_closes() {
+        let mut connection = Connection::new(
+            Cursor::new(&b"POST /echo HTTP/1.1\r\nContent-Length: 100\r\n\r\n"[..]),
+            Vec::new(),
+        );
+        let limits = Limits {
+            max_body_bytes: 10,
+            ..Limits::default()
+        };
+        let response = connection.read_request(&limits).unwrap_err().unwrap();
+        assert_eq!(response.status().code(), 413);
+        assert!(response.closing());
+    }
+
+    #[test]
+    fn reads_expectations_from_the_head() {
+        let read_head = |input: &'static [u8]| {
+            let mut connection = Connection::new(Cursor::new(input), Vec::new());
+            connection.read_head(&Limits::default())
+        };
+        let expects_continue = |input| match read_head(input) {
+            Ok(head) => head.expects_continue(),
+            Err(_) => panic!("failed to parse {input:?}"),
+        };
+        assert!(expects_continue(
+            b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nExpect: 100-Continue\r\n\r\n"
+        ));
+        // nothing to wait for
+        assert!(!expects_continue(
+            b"POST /echo HTTP/1.1\r\nContent-Length: 0\r\nExpect: 100-continue\r\n\r\n"
+        ));
+        assert!(!expects_continue(
+            b"POST /echo HTTP/1.0\r\nContent-Length: 3\r\nExpect: 100-continue\r\n\r\n"
+        ));
+
+        let Err(Some(response)) = read_head(b"GET / HTTP/1.1\r\nExpect: something\r\n\r\n") else {
+            panic!("accepted an unknown expectation");
+        };
+        assert_eq!(response.status().code(), 417);
+        assert!(response.closing());
+    }
+}
@@ -26,12 +26,68 @@ pub enum BadRequest {
     HeaderValueParseError { key: String },
     #[error("missing a target")]
     MissingTarget,
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(Box<str>),
+    #[error("multipart/form-data content type has no boundary")]
+    MissingBoundary,
+    #[error("Malformed multipart body: {0}")]
+    MalformedMultipart(&'static str),
+    #[error("Malformed url-encoded form: invalid percent-encoding")]
+    MalformedForm,
+    #[error("Malformed JSON body: {0}")]
+    MalformedJson(String),
+    #[error("Invalid WebSocket handshake: {0}")]
+    WebSocketHandshake(&'static str),
 }
 
 #[derive(Error, Debug)]
 pub enum InvalidTargetError {
     #[error("Malformed target: does not start with '/'")]
     DoesNotStartWithSlash,
+    #[error("Malformed target: not an http or https URL")]
+    UnsupportedScheme,
+}
+
+#[derive(Error, Debug)]
+pub enum MultipartError {
+    #[error(transparent)]
+    BadRequest(#[from] BadRequest),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl BadRequest {
+    /// A stable, machine-readable identifier of the kind of error, used as the problem type
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MissingMethod => "missing-method",
+            Self::UnsupportedMethod => "unsupported-method",
+            Self::NotUTF8 => "not-utf8",
+            Self::BadTarget(target_error) => target_error.kind(),
+            Self::MissingHTTPVersion => "missing-http-version",
+            Self::UnsupportedHTTPVersion => "unsupported-http-version",
+            Self::MissingCRLF => "missing-crlf",
+            Self::MissingHeader(_) => "missing-header",
+            Self::MalformedHeader => "malformed-header",
+            Self::HeaderValueParseError { .. } => "malformed-header-value",
+            Self::MissingTarget => "missing-target",
+            Self::UnsupportedMediaType(_) => "unsupported-media-type",
+            Self::MissingBoundary => "missing-multipart-boundary",
+            Self::MalformedMultipart(_) => "malformed-multipart",
+            Self::MalformedForm => "malformed-form",
+            Self::MalformedJson(_) => "malformed-json",
+            Self::WebSocketHandshake(_) => "invalid-websocket-handshake",
+        }
+    }
+}
+
+impl InvalidTargetError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::DoesNotStartWithSlash => "target-does-not-start-with-slash",
+            Self::UnsupportedScheme => "target-scheme-unsupported",
+        }
+    }
 }
 
 impl From<FromUtf8Error> for BadRequest {
@@ -50,6 +106,15 @@ impl From<io::Error> for Response {
     }
 }
 
+impl From<MultipartError> for Response {
+    fn from(error: MultipartError) -> Response {
+        match error {
+            MultipartError::BadRequest(bad_request) => bad_request.into(),
+            MultipartError::Io(io_err) => io_err.into(),
+        }
+    }
+}
+
 impl From<BadRequest> for Option<Response> {
     fn from(value: BadRequest) -> Self {
         Some(value.into())
@@ -0,0 +1,118 @@
+use crate::http::status::ResponseStatus;
+use std::{fs, path::Path};
+
+/// How error responses get their bodies
+#[derive(Debug, Default, Clone)]
+pub struct ErrorRendering {
+    /// Directory of error page templates, named by status code (e.g. `404.html` or `5xx.html`)
+    pub pages: Option<Box<Path>>,
+    /// Hides error details from responses
+    pub production: bool,
+}
+
+/// Used when `pages` is unset or has no template for a status
+const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html>
+<html>
+<head><title>{{status}} {{reason}}</title></head>
+<body>
+<h1>{{status}} {{reason}}</h1>
+<p>The request for <code>{{path}}</code> could not be completed.</p>
+<hr>
+<small>Request ID: {{request_id}}</small>
+</body>
+</html>
+";
+
+/// Renders the error page for `status`.
+///
+/// The template is the first of `<code>.html` (e.g. `404.html`) and `<class>xx.html` (e.g.
+/// `4xx.html`) found in `pages`, falling back to a built-in page. The placeholders
+/// `{{status}}`, `{{reason}}`, `{{path}}` and `{{request_id}}` are substituted, HTML-escaped.
+pub fn render(
+    status: &ResponseStatus,
+    path: &str,
+    request_id: &str,
+    pages: Option<&Path>,
+) -> String {
+    let code = status.code();
+    let template = pages
+        .and_then(|dir| {
+            [format!("{code}.html"), format!("{}xx.html", code / 100)]
+                .into_iter()
+                .find_map(|file_name| fs::read_to_string(dir.join(file_name)).ok())
+        })
+        .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+
+    // in a single pass, so that placeholders within the substituted values stay as they are
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find("{{") {
+        page.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+        let value = match &rest[2..end] {
+            "status" => code.to_string(),
+            "reason" => escape_html(status.reason()),
+            "path" => escape_html(path),
+            "request_id" => escape_html(request_id),
+            _ => {
+                // not a placeholder, though a later `{{` may start one
+                page.push_str("{{");
+                rest = &rest[2..];
+                continue;
+            }
+        };
+        page.push_str(&value);
+        rest = &rest[end + 2..];
+    }
+    page.push_str(rest);
+    page
+}
+
+pub(crate) fn escape_html(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use crate::http::status::ResponseStatus;
+
+    #[test]
+    fn substitutes_escaped_placeholders() {
+        let page = render(&ResponseStatus::NotFound, "/a<b>", "req-1", None);
+        assert!(page.contains("<title>404 Not Found</title>"), "{page}");
+        assert!(page.contains("<code>/a&lt;b&gt;</code>"), "{page}");
+        assert!(page.contains("Request ID: req-1"), "{page}");
+    }
+
+    #[test]
+    fn leaves_placeholders_within_values_alone() {
+        let path = "/{{request_id}}/{{status}}";
+        let page = render(&ResponseStatus::NotFound, path, "{{path}}", None);
+        assert!(page.contains("<code>/{{request_id}}/{{status}}</code>"), "{page}");
+        assert!(page.contains("Request ID: {{path}}"), "{page}");
+    }
+
+    #[test]
+    fn renders_templates_from_the_pages_directory() {
+        let dir = std::env::temp_dir().join(format!("error-pages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("4xx.html"), "{{status}} {{unknown}} {{reason}} {{").unwrap();
+        let page = render(&ResponseStatus::NotFound, "/", "", Some(&dir));
+        assert_eq!(page, "404 {{unknown}} Not Found {{");
+    }
+}
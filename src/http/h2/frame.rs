@@ -0,0 +1,247 @@
+//! Framing (RFC 9113, sections 4 and 6)
+
+use super::{Error, ErrorCode};
+use std::io::{self, Read, Write};
+
+pub const HEADER_LEN: usize = 9;
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+const MAX_MAX_FRAME_SIZE: u32 = (1 << 24) - 1;
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Data,
+    Headers,
+    Priority,
+    RstStream,
+    Settings,
+    PushPromise,
+    Ping,
+    GoAway,
+    WindowUpdate,
+    Continuation,
+    /// Frames of unknown types are ignored
+    Unknown(u8),
+}
+
+impl From<u8> for Kind {
+    fn from(code: u8) -> Kind {
+        match code {
+            0x0 => Kind::Data,
+            0x1 => Kind::Headers,
+            0x2 => Kind::Priority,
+            0x3 => Kind::RstStream,
+            0x4 => Kind::Settings,
+            0x5 => Kind::PushPromise,
+            0x6 => Kind::Ping,
+            0x7 => Kind::GoAway,
+            0x8 => Kind::WindowUpdate,
+            0x9 => Kind::Continuation,
+            other => Kind::Unknown(other),
+        }
+    }
+}
+
+impl Kind {
+    fn code(self) -> u8 {
+        match self {
+            Kind::Data => 0x0,
+            Kind::Headers => 0x1,
+            Kind::Priority => 0x2,
+            Kind::RstStream => 0x3,
+            Kind::Settings => 0x4,
+            Kind::PushPromise => 0x5,
+            Kind::Ping => 0x6,
+            Kind::GoAway => 0x7,
+            Kind::WindowUpdate => 0x8,
+            Kind::Continuation => 0x9,
+            Kind::Unknown(code) => code,
+        }
+    }
+}
+
+pub mod flags {
+    pub const END_STREAM: u8 = 0x1;
+    /// On SETTINGS and PING, in place of `END_STREAM`
+    pub const ACK: u8 = 0x1;
+    pub const END_HEADERS: u8 = 0x4;
+    pub const PADDED: u8 = 0x8;
+    pub const PRIORITY: u8 = 0x20;
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub kind: Kind,
+    pub flags: u8,
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: Kind, flags: u8, stream: u32, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind,
+            flags,
+            stream,
+            payload,
+        }
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Reads the next frame, refusing payloads longer than `max_size`
+    pub fn read_from(mut reader: impl Read, max_size: u32) -> Result<Frame, Error> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+        if len > max_size {
+            return Err(Error::connection(
+                ErrorCode::FrameSizeError,
+                "frame too large",
+            ));
+        }
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload)?;
+        Ok(Frame {
+            kind: header[3].into(),
+            flags: header[4],
+            // the reserved bit is ignored
+            stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                & MAX_WINDOW_SIZE,
+            payload,
+        })
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let len = (self.payload.len() as u32).to_be_bytes();
+        writer.write_all(&len[1..])?;
+        writer.write_all(&[self.kind.code(), self.flags])?;
+        writer.write_all(&self.stream.to_be_bytes())?;
+        writer.write_all(&self.payload)
+    }
+
+    /// The size on the wire
+    pub fn len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+
+    /// The payload of a DATA or HEADERS frame without its padding
+    pub fn unpadded(&self) -> Result<&[u8], Error> {
+        if !self.has(flags::PADDED) {
+            return Ok(&self.payload);
+        }
+        let (&pad_len, rest) = self
+            .payload
+            .split_first()
+            .ok_or_else(|| Error::connection(ErrorCode::FrameSizeError, "missing pad length"))?;
+        rest.len()
+            .checked_sub(usize::from(pad_len))
+            .map(|len| &rest[..len])
+            .ok_or_else(|| Error::connection(ErrorCode::ProtocolError, "padding too long"))
+    }
+
+    /// The 32-bit value a RST_STREAM or WINDOW_UPDATE frame carries
+    pub fn u32_payload(&self) -> Result<u32, Error> {
+        let bytes = <[u8; 4]>::try_from(self.payload.as_slice())
+            .map_err(|_| Error::connection(ErrorCode::FrameSizeError, "payload is not 4 bytes"))?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+}
+
+/// The parameters a peer announces through SETTINGS
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+    pub max_header_list_size: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_concurrent_streams: None,
+            initial_window_size: DEFAULT_WINDOW_SIZE,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None,
+        }
+    }
+}
+
+const ENABLE_PUSH: u16 = 0x2;
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+impl Settings {
+    /// Updates the settings from a SETTINGS payload. Unknown parameters are ignored, as are
+    /// those that only matter to a peer that compresses headers or pushes, which the server
+    /// does not.
+    pub fn apply(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() % 6 != 0 {
+            return Err(Error::connection(
+                ErrorCode::FrameSizeError,
+                "SETTINGS is not a multiple of 6 bytes",
+            ));
+        }
+        for parameter in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([parameter[0], parameter[1]]);
+            let value =
+                u32::from_be_bytes([parameter[2], parameter[3], parameter[4], parameter[5]]);
+            match id {
+                ENABLE_PUSH if value > 1 => {
+                    return Err(Error::connection(
+                        ErrorCode::ProtocolError,
+                        "invalid SETTINGS_ENABLE_PUSH",
+                    ));
+                }
+                MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = Some(value),
+                INITIAL_WINDOW_SIZE if value > MAX_WINDOW_SIZE => {
+                    return Err(Error::connection(
+                        ErrorCode::FlowControlError,
+                        "invalid SETTINGS_INITIAL_WINDOW_SIZE",
+                    ));
+                }
+                INITIAL_WINDOW_SIZE => self.initial_window_size = value,
+                MAX_FRAME_SIZE
+                    if !(DEFAULT_MAX_FRAME_SIZE..=MAX_MAX_FRAME_SIZE).contains(&value) =>
+                {
+                    return Err(Error::connection(
+                        ErrorCode::ProtocolError,
+                        "invalid SETTINGS_MAX_FRAME_SIZE",
+                    ));
+                }
+                MAX_FRAME_SIZE => self.max_frame_size = value,
+                MAX_HEADER_LIST_SIZE => self.max_header_list_size = Some(value),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The SETTINGS payload announcing everything that differs from the defaults
+    pub fn encode(&self) -> Vec<u8> {
+        let defaults = Settings::default();
+        let mut parameters = Vec::new();
+        if let Some(max) = self.max_concurrent_streams {
+            parameters.push((MAX_CONCURRENT_STREAMS, max));
+        }
+        if self.initial_window_size != defaults.initial_window_size {
+            parameters.push((INITIAL_WINDOW_SIZE, self.initial_window_size));
+        }
+        if self.max_frame_size != defaults.max_frame_size {
+            parameters.push((MAX_FRAME_SIZE, self.max_frame_size));
+        }
+        if let Some(max) = self.max_header_list_size {
+            parameters.push((MAX_HEADER_LIST_SIZE, max));
+        }
+        parameters
+            .into_iter()
+            .flat_map(|(id, value)| [id.to_be_bytes().as_slice(), &value.to_be_bytes()].concat())
+            .collect()
+    }
+}
@@ -0,0 +1,344 @@
+//! Header compression (RFC 7541)
+
+use super::{huffman, Error, ErrorCode};
+use std::collections::VecDeque;
+
+/// The static table (RFC 7541, Appendix A), indexed from 1
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// What every dynamic table entry counts towards the table's size on top of its name and value,
+/// as every field does towards the size of a header list
+const ENTRY_OVERHEAD: usize = 32;
+
+pub type HeaderField = (Vec<u8>, Vec<u8>);
+
+/// Decodes header blocks, keeping the dynamic table they build up over a connection
+pub struct Decoder {
+    /// Newest entry first, as they are indexed
+    dynamic: VecDeque<HeaderField>,
+    size: usize,
+    /// As set by the encoder, at most `max_capacity`
+    capacity: usize,
+    /// As advertised through SETTINGS_HEADER_TABLE_SIZE
+    max_capacity: usize,
+}
+
+impl Decoder {
+    pub fn new(max_capacity: usize) -> Decoder {
+        Decoder {
+            dynamic: VecDeque::new(),
+            size: 0,
+            capacity: max_capacity,
+            max_capacity,
+        }
+    }
+
+    /// Decodes a complete header block, or `None` if its header list is larger than
+    /// `max_list_size`. Fields past the limit are still decoded, to keep the dynamic table in
+    /// step, but not kept, as a small block of indexed fields can expand to a huge list. Any
+    /// error leaves the dynamic table out of step with the peer's, so it fails the whole
+    /// connection.
+    pub fn decode(
+        &mut self,
+        mut block: &[u8],
+        max_list_size: usize,
+    ) -> Result<Option<Vec<HeaderField>>, Error> {
+        let mut fields = Some(Vec::new());
+        let mut list_size = 0;
+        while let Some(&first) = block.first() {
+            let field = if first & 0x80 != 0 {
+                let index = integer(&mut block, 7)?;
+                self.entry(index)?
+            } else if first & 0xc0 == 0x40 {
+                let field = self.literal(&mut block, 6)?;
+                self.insert(field.clone());
+                field
+            } else if first & 0xe0 == 0x20 {
+                // size updates must come before the first field of a block
+                if list_size > 0 {
+                    return Err(compression_error("table size update after a header field"));
+                }
+                let capacity = integer(&mut block, 5)?;
+                if capacity > self.max_capacity {
+                    return Err(compression_error("table size update above the maximum"));
+                }
+                self.capacity = capacity;
+                self.evict_for(0);
+                continue;
+            } else {
+                // without indexing, or never indexed
+                self.literal(&mut block, 4)?
+            };
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            match &mut fields {
+                Some(kept) if list_size <= max_list_size => kept.push(field),
+                _ => fields = None,
+            }
+        }
+        Ok(fields)
+    }
+
+    fn entry(&self, index: usize) -> Result<HeaderField, Error> {
+        if let Some(&(name, value)) = index.checked_sub(1).and_then(|i| STATIC_TABLE.get(i)) {
+            return Ok((name.into(), value.into()));
+        }
+        index
+            .checked_sub(STATIC_TABLE.len() + 1)
+            .and_then(|i| self.dynamic.get(i))
+            .cloned()
+            .ok_or_else(|| compression_error("header field index out of range"))
+    }
+
+    /// A literal field whose name is either indexed in `prefix_bits` or follows as a string
+    fn literal(&self, block: &mut &[u8], prefix_bits: u8) -> Result<HeaderField, Error> {
+        let name = match integer(block, prefix_bits)? {
+            0 => string(block)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, string(block)?))
+    }
+
+    fn insert(&mut self, field: HeaderField) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.evict_for(size);
+        // an entry larger than the whole table just empties it
+        if size <= self.capacity {
+            self.size += size;
+            self.dynamic.push_front(field);
+        }
+    }
+
+    /// Evicts the oldest entries until `size` more fits
+    fn evict_for(&mut self, size: usize) {
+        while self.size + size > self.capacity {
+            let Some((name, value)) = self.dynamic.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// Encodes a header block. Every field is a literal that isn't indexed, so there is no encoder
+/// state to keep in step with the peer's SETTINGS_HEADER_TABLE_SIZE.
+pub fn encode<'a>(fields: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in fields {
+        block.push(0);
+        encode_string(&mut block, name);
+        encode_string(&mut block, value);
+    }
+    block
+}
+
+fn encode_string(block: &mut Vec<u8>, string: &[u8]) {
+    encode_integer(block, 0, 7, string.len());
+    block.extend_from_slice(string);
+}
+
+/// Appends `value` in an integer representation whose first octet starts with `flags` and
+/// leaves `prefix_bits` for the value
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix_bits: u8, mut value: usize) {
+    let max_prefix = (1 << prefix_bits) - 1;
+    if value < max_prefix {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Reads an integer whose first octet leaves `prefix_bits` for it
+fn integer(block: &mut &[u8], prefix_bits: u8) -> Result<usize, Error> {
+    let truncated = || compression_error("truncated integer");
+    let (&first, rest) = block.split_first().ok_or_else(truncated)?;
+    *block = rest;
+    let max_prefix = (1 << prefix_bits) - 1;
+    let mut value = usize::from(first) & max_prefix;
+    if value < max_prefix {
+        return Ok(value);
+    }
+    for shift in (0..).step_by(7) {
+        let (&byte, rest) = block.split_first().ok_or_else(truncated)?;
+        *block = rest;
+        // anything longer is no sensible length or index, and would overflow
+        if shift > 28 {
+            return Err(compression_error("integer too large"));
+        }
+        value += usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+fn string(block: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    let huffman_coded = block.first().is_some_and(|first| first & 0x80 != 0);
+    let len = integer(block, 7)?;
+    if len > block.len() {
+        return Err(compression_error("truncated string"));
+    }
+    let (string, rest) = block.split_at(len);
+    *block = rest;
+    if huffman_coded {
+        huffman::decode(string).ok_or_else(|| compression_error("invalid Huffman code"))
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+fn compression_error(reason: &'static str) -> Error {
+    Error::connection(ErrorCode::CompressionError, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, Decoder};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs
+            .iter()
+            .map(|(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    /// RFC 7541, appendix C.4: requests with Huffman coding, sharing a dynamic table
+    #[test]
+    fn decodes_the_rfc_examples() {
+        let mut decoder = Decoder::new(4096);
+        let first = decoder
+            .decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff"), usize::MAX)
+            .unwrap();
+        assert_eq!(
+            first,
+            Some(fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ]))
+        );
+        let second = decoder.decode(&hex("828684be5886a8eb10649cbf"), usize::MAX).unwrap();
+        assert_eq!(
+            second,
+            Some(fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let headers = fields(&[(":status", "200"), ("x-long", &"a".repeat(300))]);
+        let block = encode(headers.iter().map(|(n, v)| (n.as_slice(), v.as_slice())));
+        assert_eq!(Decoder::new(4096).decode(&block, usize::MAX).unwrap(), Some(headers));
+    }
+
+    #[test]
+    fn indexes_the_static_table() {
+        let decoded = Decoder::new(4096).decode(&[0x8f, 0x90, 0xbd], usize::MAX).unwrap();
+        let expected = [
+            ("accept-charset", ""),
+            ("accept-encoding", "gzip, deflate"),
+            ("www-authenticate", ""),
+        ];
+        assert_eq!(decoded, Some(fields(&expected)));
+    }
+
+    #[test]
+    fn stops_keeping_fields_past_the_list_size() {
+        let mut decoder = Decoder::new(4096);
+        // adds `x` to the dynamic table, then refers to it over and over
+        let mut block = vec![0x40, 0x01, b'x', 0x00];
+        block.extend([0xbe; 1000]);
+        assert_eq!(decoder.decode(&block, 10 * 33).unwrap(), None);
+        // the rest of the block was decoded all the same, so the table is still in step
+        assert_eq!(decoder.decode(&[0xbe], 33).unwrap(), Some(fields(&[("x", "")])));
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices_and_truncated_blocks() {
+        assert!(Decoder::new(4096).decode(&[0x80], usize::MAX).is_err());
+        assert!(Decoder::new(4096).decode(&[0xbe], usize::MAX).is_err());
+        assert!(Decoder::new(4096).decode(&[0x40, 0x05, b'a'], usize::MAX).is_err());
+    }
+}
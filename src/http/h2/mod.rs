@@ -0,0 +1,140 @@
+//! HTTP/2 (RFC 9113), spoken on connections that open with the connection preface: over TLS
+//! once negotiated through ALPN, in cleartext by clients that know beforehand that the server
+//! speaks it, or after an HTTP/1.1 request asked to upgrade with `Upgrade: h2c`.
+//!
+//! Streams are handled one at a time, in the order their requests complete, but their responses
+//! are interleaved as flow control allows.
+//!
+//! Known limitation: handlers run on the connection's own thread, so a slow one stalls every
+//! other stream of its connection, both those waiting to be handled and those whose responses
+//! are still being sent. Clients that need independent requests should open more connections.
+
+mod frame;
+mod hpack;
+mod huffman;
+mod session;
+
+use crate::http::{
+    connection::Connection,
+    request::{Limits, Request},
+    response::Response,
+    BytesSent,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use frame::Settings;
+use std::io::{self, BufRead, Read, Write};
+use thiserror::Error;
+
+/// What a client sends first on an HTTP/2 connection
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The ALPN protocol ID of HTTP/2 over TLS
+pub const ALPN: &[u8] = b"h2";
+
+/// What the server does with each request on a connection
+pub trait Handler {
+    /// Whatever `sent` needs to know about a request once its response is sent
+    type Context;
+
+    /// Answers a request, or a response for one that was rejected while being received
+    fn handle(&mut self, request: Result<Request, Response>) -> (Response, Self::Context);
+
+    fn sent(&mut self, context: Self::Context, status: u16, bytes_sent: BytesSent);
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Ends the connection with GOAWAY
+    #[error("{code:?}: {reason}")]
+    Connection {
+        code: ErrorCode,
+        reason: &'static str,
+    },
+    /// Resets a single stream with RST_STREAM
+    #[error("stream {stream}: {code:?}: {reason}")]
+    Stream {
+        stream: u32,
+        code: ErrorCode,
+        reason: &'static str,
+    },
+}
+
+impl Error {
+    fn connection(code: ErrorCode, reason: &'static str) -> Error {
+        Error::Connection { code, reason }
+    }
+
+    fn stream(stream: u32, code: ErrorCode, reason: &'static str) -> Error {
+        Error::Stream {
+            stream,
+            code,
+            reason,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+    EnhanceYourCalm = 0xb,
+}
+
+/// Waits for the client to send something, and tells whether it opened with the HTTP/2 preface
+/// as far as it has arrived. Nothing is consumed.
+pub fn starts_with_preface<R: Read>(reader: &mut io::BufReader<R>) -> io::Result<bool> {
+    let buf = reader.fill_buf()?;
+    if buf.is_empty() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(sniff_preface(buf).unwrap_or(false))
+}
+
+/// Whether `buf`, the first bytes a client sent, opens with the HTTP/2 preface as far as it goes,
+/// or `None` if it is too short to tell
+pub fn sniff_preface(buf: &[u8]) -> Option<bool> {
+    let matches = PREFACE.starts_with(&buf[..buf.len().min(PREFACE.len())]);
+    // enough to tell it apart from any HTTP/1 method
+    (buf.len() >= 4 || !matches).then_some(matches)
+}
+
+/// The settings a request asking to upgrade to h2c announces in `HTTP2-Settings`, if it asks to
+/// upgrade and may (RFC 7540, section 3.2)
+pub fn upgrade_settings(request: &Request) -> Option<Vec<u8>> {
+    let upgrade = request.header("Upgrade")?;
+    let asks = upgrade
+        .split(',')
+        .any(|protocol| protocol.trim().eq_ignore_ascii_case("h2c"));
+    if !asks {
+        return None;
+    }
+    let settings = URL_SAFE_NO_PAD
+        .decode(request.header("HTTP2-Settings")?.trim())
+        .ok()?;
+    Settings::default().apply(&settings).ok()?;
+    Some(settings)
+}
+
+/// Serves the connection over HTTP/2 until either side ends it. If it was upgraded from
+/// HTTP/1.1, `upgrade` holds the request that asked and the settings it came with, which is then
+/// answered as stream 1.
+pub fn serve<R: Read, W: Write, H: Handler>(
+    connection: Connection<R, W>,
+    upgrade: Option<(Request, Vec<u8>)>,
+    limits: &Limits,
+    handler: H,
+) {
+    let (reader, writer) = connection.into_parts();
+    let session = session::Session::new(reader, writer, limits, handler);
+    if let Err(e) = session.run(upgrade) {
+        log::info!("HTTP/2 connection failed: {e}");
+    }
+}
@@ -0,0 +1,755 @@
+//! One HTTP/2 connection: its streams, flow control windows, and header compression state
+
+use super::{
+    frame::{self, flags, Frame, Kind, Settings},
+    hpack::{self, HeaderField},
+    Error, ErrorCode, Handler, PREFACE,
+};
+use crate::http::{
+    request::{Limits, Request},
+    response::{client_error, Response},
+    BytesSent, Version,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+/// How many streams a client may have open at once
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+/// How many bodies of the largest size allowed a connection may have buffered at once, across
+/// all of its streams
+const MAX_BUFFERED_BODIES: usize = 4;
+/// The size of the dynamic table the client may compress headers with, the protocol's default
+const HEADER_TABLE_SIZE: usize = 4096;
+/// Headers that only concern a single HTTP/1 connection, and so have no place in HTTP/2
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub(super) struct Session<'a, R, W: Write, H: Handler> {
+    reader: BufReader<R>,
+    writer: BufWriter<W>,
+    limits: &'a Limits,
+    handler: H,
+    decoder: hpack::Decoder,
+    /// What the client announced
+    remote: Settings,
+    streams: BTreeMap<u32, Stream<H::Context>>,
+    /// The highest stream the client opened, reported by GOAWAY as the last one processed
+    last_stream: u32,
+    /// How much the client is willing to receive on the connection as a whole
+    send_window: i64,
+    /// Set once the client sent GOAWAY, to end the connection when no streams remain
+    going_away: bool,
+}
+
+struct Stream<C> {
+    /// The request as far as it has been received, until it's dispatched
+    incoming: Option<Incoming>,
+    /// Whether the client has ended its side of the stream
+    remote_closed: bool,
+    send_window: i64,
+    /// The response, once the request has been handled, until it's sent in full
+    outgoing: Option<Outgoing<C>>,
+}
+
+struct Incoming {
+    fields: Vec<HeaderField>,
+    body: Vec<u8>,
+    wire_size: usize,
+}
+
+struct Outgoing<C> {
+    body: Vec<u8>,
+    /// How much of `body` was sent
+    offset: usize,
+    status: u16,
+    bytes_sent: BytesSent,
+    context: C,
+}
+
+impl<'a, R: Read, W: Write, H: Handler> Session<'a, R, W, H> {
+    pub fn new(reader: BufReader<R>, writer: W, limits: &'a Limits, handler: H) -> Self {
+        Session {
+            reader,
+            writer: BufWriter::new(writer),
+            limits,
+            handler,
+            decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+            remote: Settings::default(),
+            streams: BTreeMap::new(),
+            last_stream: 0,
+            send_window: i64::from(frame::DEFAULT_WINDOW_SIZE),
+            going_away: false,
+        }
+    }
+
+    pub fn run(mut self, upgrade: Option<(Request, Vec<u8>)>) -> Result<(), Error> {
+        let local = Settings {
+            max_concurrent_streams: Some(MAX_CONCURRENT_STREAMS),
+            max_header_list_size: u32::try_from(self.limits.max_header_bytes).ok(),
+            ..Settings::default()
+        };
+        self.write(Frame::new(Kind::Settings, 0, 0, local.encode()))?;
+
+        let result = self
+            .answer_upgrade(upgrade)
+            .and_then(|()| self.serve_frames());
+        let (code, reason) = match result {
+            Ok(()) => (ErrorCode::NoError, ""),
+            Err(Error::Connection { code, reason }) => {
+                log::debug!("closing HTTP/2 connection: {code:?}: {reason}");
+                (code, reason)
+            }
+            Err(e) => return Err(e),
+        };
+        let mut payload = Vec::with_capacity(8 + reason.len());
+        payload.extend_from_slice(&self.last_stream.to_be_bytes());
+        payload.extend_from_slice(&(code as u32).to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        self.write(Frame::new(Kind::GoAway, 0, 0, payload))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Answers the request that asked to upgrade as stream 1, which the client has already
+    /// ended its side of
+    fn answer_upgrade(&mut self, upgrade: Option<(Request, Vec<u8>)>) -> Result<(), Error> {
+        let Some((request, settings)) = upgrade else {
+            return Ok(());
+        };
+        self.remote.apply(&settings)?;
+        self.last_stream = 1;
+        let stream = Stream::new(true, &self.remote);
+        self.streams.insert(1, stream);
+        self.dispatch(1, Ok(request))
+    }
+
+    fn serve_frames(&mut self) -> Result<(), Error> {
+        // an upgraded client waits for the response to its request before sending the preface
+        self.writer.flush()?;
+        let mut preface = [0; PREFACE.len()];
+        self.reader.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(Error::connection(
+                ErrorCode::ProtocolError,
+                "invalid preface",
+            ));
+        }
+        let first = self.read_frame()?;
+        if first.kind != Kind::Settings || first.has(flags::ACK) {
+            return Err(Error::connection(
+                ErrorCode::ProtocolError,
+                "expected SETTINGS first",
+            ));
+        }
+        self.on_frame(first)?;
+
+        loop {
+            self.send_data()?;
+            self.writer.flush()?;
+            if self.going_away && self.streams.is_empty() {
+                return Ok(());
+            }
+            let frame = match self.read_frame() {
+                Ok(frame) => frame,
+                // the client hung up, or the connection sat idle for too long
+                Err(Error::Io(e)) if is_hang_up(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match self.on_frame(frame) {
+                Err(Error::Stream {
+                    stream,
+                    code,
+                    reason,
+                }) => {
+                    log::debug!("resetting stream {stream}: {code:?}: {reason}");
+                    self.reset(stream, code)?;
+                }
+                result => result?,
+            }
+        }
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        log::trace!("received {:?} frame on stream {}", frame.kind, frame.stream);
+        match frame.kind {
+            Kind::Data => self.on_data(frame),
+            Kind::Headers => self.on_headers(frame),
+            Kind::Priority if frame.stream == 0 => Err(protocol_error("PRIORITY on stream 0")),
+            Kind::Priority if frame.payload.len() != 5 => Err(Error::stream(
+                frame.stream,
+                ErrorCode::FrameSizeError,
+                "PRIORITY is not 5 bytes",
+            )),
+            // priorities are only hints, and streams are answered in order anyway
+            Kind::Priority => Ok(()),
+            Kind::RstStream => self.on_rst_stream(frame),
+            Kind::Settings => self.on_settings(frame),
+            Kind::PushPromise => Err(protocol_error("clients can't push")),
+            Kind::Ping => self.on_ping(frame),
+            Kind::GoAway if frame.stream != 0 => Err(protocol_error("GOAWAY on a stream")),
+            Kind::GoAway => {
+                self.going_away = true;
+                Ok(())
+            }
+            Kind::WindowUpdate => self.on_window_update(frame),
+            Kind::Continuation => Err(protocol_error("CONTINUATION without HEADERS")),
+            Kind::Unknown(_) => Ok(()),
+        }
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<(), Error> {
+        let id = frame.stream;
+        if id % 2 == 0 {
+            return Err(protocol_error("HEADERS on a stream clients can't open"));
+        }
+        let end_stream = frame.has(flags::END_STREAM);
+        let mut wire_size = frame.len();
+        let mut fragment = frame.unpadded()?;
+        if frame.has(flags::PRIORITY) {
+            let Some((dependency, rest)) = fragment.split_first_chunk::<4>() else {
+                return Err(Error::connection(
+                    ErrorCode::FrameSizeError,
+                    "HEADERS too short",
+                ));
+            };
+            if u32::from_be_bytes(*dependency) & frame::MAX_WINDOW_SIZE == id {
+                return Err(Error::stream(
+                    id,
+                    ErrorCode::ProtocolError,
+                    "stream depends on itself",
+                ));
+            }
+            // the weight
+            fragment = rest.get(1..).unwrap_or_default();
+        }
+
+        let mut block = fragment.to_vec();
+        let mut end_headers = frame.has(flags::END_HEADERS);
+        while !end_headers {
+            let next = self.read_frame()?;
+            if next.kind != Kind::Continuation || next.stream != id {
+                return Err(protocol_error("expected CONTINUATION"));
+            }
+            wire_size += next.len();
+            block.extend_from_slice(&next.payload);
+            if block.len() > self.limits.max_header_bytes {
+                return Err(Error::connection(
+                    ErrorCode::EnhanceYourCalm,
+                    "header block too large",
+                ));
+            }
+            end_headers = next.has(flags::END_HEADERS);
+        }
+        // even for streams about to be refused, to keep the dynamic table in step
+        let fields = self.decoder.decode(&block, self.limits.max_header_bytes)?;
+
+        if id <= self.last_stream {
+            // trailers, which nothing needs
+            let Some(stream) = self.streams.get_mut(&id) else {
+                return Err(Error::connection(
+                    ErrorCode::StreamClosed,
+                    "HEADERS on a closed stream",
+                ));
+            };
+            if stream.remote_closed {
+                return Err(Error::stream(
+                    id,
+                    ErrorCode::StreamClosed,
+                    "HEADERS after END_STREAM",
+                ));
+            }
+            if !end_stream {
+                return Err(Error::stream(
+                    id,
+                    ErrorCode::ProtocolError,
+                    "trailers without END_STREAM",
+                ));
+            }
+            stream.remote_closed = true;
+            return self.finish_request(id);
+        }
+
+        self.last_stream = id;
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS as usize {
+            return Err(Error::stream(
+                id,
+                ErrorCode::RefusedStream,
+                "too many open streams",
+            ));
+        }
+        let mut stream = Stream::new(end_stream, &self.remote);
+        let Some(fields) = fields else {
+            self.streams.insert(id, stream);
+            return self.dispatch(id, Err(client_error::request_header_fields_too_large()));
+        };
+        stream.incoming = Some(Incoming {
+            fields,
+            body: Vec::new(),
+            wire_size,
+        });
+        self.streams.insert(id, stream);
+        if end_stream {
+            self.finish_request(id)?;
+        }
+        Ok(())
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), Error> {
+        let id = frame.stream;
+        if id == 0 || id > self.last_stream {
+            return Err(protocol_error("DATA on an idle stream"));
+        }
+        // Bodies are read in full before handling anyway, so the windows are replenished right
+        // away and never hold less than their initial size. Padding counts too. What is buffered
+        // is bounded by refusing streams instead.
+        let len = frame.payload.len();
+        if len > frame::DEFAULT_WINDOW_SIZE as usize {
+            return Err(Error::connection(
+                ErrorCode::FlowControlError,
+                "DATA beyond the window",
+            ));
+        }
+        if len > 0 {
+            self.write(window_update(0, len))?;
+        }
+
+        let data = frame.unpadded()?;
+        let buffered: usize = self
+            .streams
+            .values()
+            .filter_map(|stream| stream.incoming.as_ref())
+            .map(|incoming| incoming.body.len())
+            .sum();
+        let Some(stream) = self.streams.get_mut(&id) else {
+            // e.g. sent before the client noticed the stream was reset
+            log::trace!("ignoring DATA on closed stream {id}");
+            return Ok(());
+        };
+        if stream.remote_closed {
+            return Err(Error::stream(
+                id,
+                ErrorCode::StreamClosed,
+                "DATA after END_STREAM",
+            ));
+        }
+        let max_buffered = self.limits.max_body_bytes.saturating_mul(MAX_BUFFERED_BODIES);
+        if stream.incoming.is_some() && buffered + data.len() > max_buffered {
+            // not processed at all, so the client can safely retry it once others are done
+            return Err(Error::stream(
+                id,
+                ErrorCode::RefusedStream,
+                "too much buffered across streams",
+            ));
+        }
+        let end_stream = frame.has(flags::END_STREAM);
+        stream.remote_closed = end_stream;
+        // a request answered early has the rest of its body dropped
+        let too_large = stream.incoming.as_mut().is_some_and(|incoming| {
+            incoming.body.extend_from_slice(data);
+            incoming.wire_size += frame.len();
+            incoming.body.len() > self.limits.max_body_bytes
+        });
+        if !end_stream && len > 0 {
+            self.write(window_update(id, len))?;
+        }
+
+        if too_large {
+            self.streams
+                .get_mut(&id)
+                .map(|stream| stream.incoming.take());
+            return self.dispatch(id, Err(client_error::content_too_large()));
+        }
+        if end_stream {
+            self.finish_request(id)?;
+        }
+        Ok(())
+    }
+
+    fn on_rst_stream(&mut self, frame: Frame) -> Result<(), Error> {
+        if frame.stream == 0 || frame.stream > self.last_stream {
+            return Err(protocol_error("RST_STREAM on an idle stream"));
+        }
+        let code = frame.u32_payload()?;
+        log::debug!(
+            "client reset stream {} with error code {code:#x}",
+            frame.stream
+        );
+        self.streams.remove(&frame.stream);
+        Ok(())
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<(), Error> {
+        if frame.stream != 0 {
+            return Err(protocol_error("SETTINGS on a stream"));
+        }
+        if frame.has(flags::ACK) {
+            if !frame.payload.is_empty() {
+                return Err(Error::connection(
+                    ErrorCode::FrameSizeError,
+                    "SETTINGS ACK with a payload",
+                ));
+            }
+            return Ok(());
+        }
+        let previous_window = self.remote.initial_window_size;
+        self.remote.apply(&frame.payload)?;
+        let delta = i64::from(self.remote.initial_window_size) - i64::from(previous_window);
+        for stream in self.streams.values_mut() {
+            stream.send_window += delta;
+            if stream.send_window > i64::from(frame::MAX_WINDOW_SIZE) {
+                return Err(Error::connection(
+                    ErrorCode::FlowControlError,
+                    "window too large",
+                ));
+            }
+        }
+        self.write(Frame::new(Kind::Settings, flags::ACK, 0, Vec::new()))
+    }
+
+    fn on_ping(&mut self, frame: Frame) -> Result<(), Error> {
+        if frame.stream != 0 {
+            return Err(protocol_error("PING on a stream"));
+        }
+        if frame.payload.len() != 8 {
+            return Err(Error::connection(
+                ErrorCode::FrameSizeError,
+                "PING is not 8 bytes",
+            ));
+        }
+        if frame.has(flags::ACK) {
+            return Ok(());
+        }
+        self.write(Frame::new(Kind::Ping, flags::ACK, 0, frame.payload))
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), Error> {
+        let increment = i64::from(frame.u32_payload()? & frame::MAX_WINDOW_SIZE);
+        let max = i64::from(frame::MAX_WINDOW_SIZE);
+        if frame.stream == 0 {
+            if increment == 0 {
+                return Err(protocol_error("WINDOW_UPDATE by 0"));
+            }
+            self.send_window += increment;
+            if self.send_window > max {
+                return Err(Error::connection(
+                    ErrorCode::FlowControlError,
+                    "window too large",
+                ));
+            }
+            return Ok(());
+        }
+
+        if frame.stream > self.last_stream {
+            return Err(protocol_error("WINDOW_UPDATE on an idle stream"));
+        }
+        if increment == 0 {
+            return Err(Error::stream(
+                frame.stream,
+                ErrorCode::ProtocolError,
+                "WINDOW_UPDATE by 0",
+            ));
+        }
+        // streams already closed may still see updates the client sent before noticing
+        if let Some(stream) = self.streams.get_mut(&frame.stream) {
+            stream.send_window += increment;
+            if stream.send_window > max {
+                return Err(Error::stream(
+                    frame.stream,
+                    ErrorCode::FlowControlError,
+                    "window too large",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispatches the stream's request if it has been received in full and not dispatched yet
+    fn finish_request(&mut self, id: u32) -> Result<(), Error> {
+        let Some(incoming) = self
+            .streams
+            .get_mut(&id)
+            .and_then(|stream| stream.incoming.take())
+        else {
+            return Ok(());
+        };
+        let request = incoming.into_request(id)?;
+        self.dispatch(id, request)
+    }
+
+    /// Handles the request and sends the response's headers. Its body follows as flow control
+    /// allows. The handler runs right here, holding up the rest of the connection until it
+    /// returns; see the module documentation.
+    fn dispatch(&mut self, id: u32, request: Result<Request, Response>) -> Result<(), Error> {
+        let (response, context) = self.handler.handle(request);
+        let (status, headers, body) = response.into_parts();
+        let status = status.code();
+
+        let status_text = status.to_string();
+        let headers: Vec<(String, Box<str>)> = headers
+            .into_iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .filter(|(name, _)| !CONNECTION_SPECIFIC.contains(&name.as_str()))
+            .collect();
+        let fields = [(&b":status"[..], status_text.as_bytes())]
+            .into_iter()
+            .chain(
+                headers
+                    .iter()
+                    .map(|(name, value)| (name.as_bytes(), value.as_bytes())),
+            );
+        let block = hpack::encode(fields);
+
+        let end_stream = body.is_empty();
+        let bytes_sent = BytesSent {
+            total: self.write_headers(id, block, end_stream)?,
+            body: 0,
+        };
+        let outgoing = Outgoing {
+            body,
+            offset: 0,
+            status,
+            bytes_sent,
+            context,
+        };
+        if end_stream {
+            return self.finished(id, outgoing);
+        }
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.outgoing = Some(outgoing);
+        }
+        Ok(())
+    }
+
+    /// Sends a header block, in CONTINUATION frames after the first if it doesn't fit one
+    fn write_headers(&mut self, id: u32, block: Vec<u8>, end_stream: bool) -> Result<u64, Error> {
+        let chunks: Vec<&[u8]> = match block.is_empty() {
+            true => vec![&[]],
+            false => block.chunks(self.remote.max_frame_size as usize).collect(),
+        };
+        let mut bytes_sent = 0;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let (kind, mut frame_flags) = match i {
+                0 if end_stream => (Kind::Headers, flags::END_STREAM),
+                0 => (Kind::Headers, 0),
+                _ => (Kind::Continuation, 0),
+            };
+            if i == chunks.len() - 1 {
+                frame_flags |= flags::END_HEADERS;
+            }
+            let frame = Frame::new(kind, frame_flags, id, chunk.to_vec());
+            bytes_sent += frame.len() as u64;
+            self.write(frame)?;
+        }
+        Ok(bytes_sent)
+    }
+
+    /// Sends what the flow control windows allow of every pending response body, a frame per
+    /// stream at a time so that they share the connection
+    fn send_data(&mut self) -> Result<(), Error> {
+        let max_frame_size = self.remote.max_frame_size as usize;
+        loop {
+            let mut progressed = false;
+            let pending: Vec<u32> = self
+                .streams
+                .iter()
+                .filter(|(_, stream)| stream.outgoing.is_some())
+                .map(|(&id, _)| id)
+                .collect();
+            for id in pending {
+                let Some(stream) = self.streams.get_mut(&id) else {
+                    continue;
+                };
+                let Some(outgoing) = stream.outgoing.as_mut() else {
+                    continue;
+                };
+                let remaining = outgoing.body.len() - outgoing.offset;
+                let window = self.send_window.min(stream.send_window).max(0) as usize;
+                let len = remaining.min(window).min(max_frame_size);
+                if len == 0 {
+                    continue;
+                }
+                let end_stream = len == remaining;
+                let chunk = outgoing.body[outgoing.offset..][..len].to_vec();
+                let frame = Frame::new(
+                    Kind::Data,
+                    if end_stream { flags::END_STREAM } else { 0 },
+                    id,
+                    chunk,
+                );
+                outgoing.offset += len;
+                outgoing.bytes_sent.total += frame.len() as u64;
+                outgoing.bytes_sent.body += len as u64;
+                stream.send_window -= len as i64;
+                self.send_window -= len as i64;
+                frame.write_to(&mut self.writer)?;
+                progressed = true;
+
+                if end_stream {
+                    if let Some(outgoing) = stream.outgoing.take() {
+                        self.finished(id, outgoing)?;
+                    }
+                }
+            }
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Closes a stream whose response has been sent in full
+    fn finished(&mut self, id: u32, outgoing: Outgoing<H::Context>) -> Result<(), Error> {
+        self.handler
+            .sent(outgoing.context, outgoing.status, outgoing.bytes_sent);
+        let remote_closed = self
+            .streams
+            .remove(&id)
+            .map_or(true, |stream| stream.remote_closed);
+        if !remote_closed {
+            // answered before the client finished sending, e.g. with 413
+            self.write(Frame::new(
+                Kind::RstStream,
+                0,
+                id,
+                (ErrorCode::NoError as u32).to_be_bytes().to_vec(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, id: u32, code: ErrorCode) -> Result<(), Error> {
+        self.streams.remove(&id);
+        self.write(Frame::new(
+            Kind::RstStream,
+            0,
+            id,
+            (code as u32).to_be_bytes().to_vec(),
+        ))
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, Error> {
+        Frame::read_from(&mut self.reader, frame::DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    fn write(&mut self, frame: Frame) -> Result<(), Error> {
+        log::trace!("sending {:?} frame on stream {}", frame.kind, frame.stream);
+        Ok(frame.write_to(&mut self.writer)?)
+    }
+}
+
+impl<C> Stream<C> {
+    fn new(remote_closed: bool, remote: &Settings) -> Self {
+        Stream {
+            incoming: None,
+            remote_closed,
+            send_window: i64::from(remote.initial_window_size),
+            outgoing: None,
+        }
+    }
+}
+
+impl Incoming {
+    /// Checks the request is well-formed (RFC 9113, section 8.1.1) and turns it into a
+    /// `Request`, or into the response rejecting it
+    fn into_request(self, id: u32) -> Result<Result<Request, Response>, Error> {
+        let malformed = |reason| Error::stream(id, ErrorCode::ProtocolError, reason);
+        let (mut method, mut path, mut scheme, mut authority) = (None, None, None, None);
+        let mut headers: HashMap<Box<str>, Box<str>> = HashMap::new();
+        for (name, value) in self.fields {
+            let value = String::from_utf8_lossy(&value).into_owned();
+            if let Some(pseudo) = name.strip_prefix(b":") {
+                if !headers.is_empty() {
+                    return Err(malformed("pseudo-header after a regular one"));
+                }
+                let slot = match pseudo {
+                    b"method" => &mut method,
+                    b"path" => &mut path,
+                    b"scheme" => &mut scheme,
+                    b"authority" => &mut authority,
+                    _ => return Err(malformed("unknown pseudo-header")),
+                };
+                if slot.replace(value).is_some() {
+                    return Err(malformed("repeated pseudo-header"));
+                }
+                continue;
+            }
+
+            if name.iter().any(u8::is_ascii_uppercase) {
+                return Err(malformed("uppercase header name"));
+            }
+            let name = String::from_utf8_lossy(&name);
+            if CONNECTION_SPECIFIC.contains(&&*name) || (name == "te" && value != "trailers") {
+                return Err(malformed("connection-specific header"));
+            }
+            let name = capitalized(&name);
+            // split up for better compression, see RFC 9113, section 8.2.3
+            let separator = if name == "Cookie" { "; " } else { ", " };
+            headers
+                .entry(name.into())
+                .and_modify(|existing| *existing = format!("{existing}{separator}{value}").into())
+                .or_insert_with(|| value.into());
+        }
+        let (Some(method), Some(path), Some(_)) = (method, path, scheme) else {
+            return Err(malformed("missing pseudo-header"));
+        };
+        if let Some(authority) = authority {
+            headers.entry("Host".into()).or_insert(authority.into());
+        }
+        if let Some(length) = headers.remove("Content-Length") {
+            if length.parse() != Ok(self.body.len()) {
+                return Err(malformed("content-length differs from the body"));
+            }
+        }
+
+        let body = self.body.into_boxed_slice();
+        Ok(
+            Request::from_parts(&method, &path, Version::Ver2, headers, body, self.wire_size)
+                .map_err(Response::from),
+        )
+    }
+}
+
+/// Handlers look headers up as HTTP/1 clients conventionally spell them, e.g. `User-Agent`
+fn capitalized(name: &str) -> String {
+    let mut capitalized = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        capitalized.push(if word_start {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        });
+        word_start = c == '-';
+    }
+    capitalized
+}
+
+fn window_update(stream: u32, increment: usize) -> Frame {
+    let increment = increment as u32;
+    Frame::new(
+        Kind::WindowUpdate,
+        0,
+        stream,
+        increment.to_be_bytes().to_vec(),
+    )
+}
+
+fn protocol_error(reason: &'static str) -> Error {
+    Error::connection(ErrorCode::ProtocolError, reason)
+}
+
+fn is_hang_up(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,173 @@
+/// A parsed media type such as found in a `Content-Type` header, e.g.
+/// `multipart/form-data; boundary="abc"`
+#[derive(Debug)]
+pub struct MediaType<'a> {
+    essence: Box<str>,
+    params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> MediaType<'a> {
+    pub fn parse(value: &'a str) -> MediaType<'a> {
+        let mut split = split_unquoted(value, ';');
+        let essence = split
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase()
+            .into();
+
+        let params = split
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.trim(), unquote(value.trim())))
+            .collect();
+
+        MediaType { essence, params }
+    }
+
+    /// The `type/subtype` part, lowercased and without parameters
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    /// Parameter names are matched case-insensitively
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Splits `value` on every `separator` outside of quoted strings
+fn split_unquoted(value: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    value.split(move |c| {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator => return !quoted,
+            _ => {}
+        }
+        false
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Chooses the representation the client prefers from those `available`, according to the
+/// q-values of the given `Accept` header. Ties are broken by the order of `available`.
+///
+/// Returns `None` if the client accepts none of them. A missing header accepts anything.
+pub fn negotiate<'a>(accept: Option<&str>, available: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = accept.filter(|a| !a.trim().is_empty()) else {
+        return available.first().copied();
+    };
+
+    // a quoted parameter may itself contain commas
+    let ranges: Vec<(MediaType, f32)> = split_unquoted(accept, ',')
+        .map(|range| {
+            let media_type = MediaType::parse(range);
+            let quality = media_type
+                .param("q")
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (media_type, quality)
+        })
+        .collect();
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for &offered in available {
+        let (offered_type, offered_subtype) = offered.split_once('/').unwrap_or((offered, ""));
+
+        // the most specific matching range decides the quality
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, quality)| {
+                let (range_type, range_subtype) = range.essence().split_once('/')?;
+                let specificity = match (range_type, range_subtype) {
+                    ("*", "*") => 0,
+                    (t, "*") if t.eq_ignore_ascii_case(offered_type) => 1,
+                    (t, s)
+                        if t.eq_ignore_ascii_case(offered_type)
+                            && s.eq_ignore_ascii_case(offered_subtype) =>
+                    {
+                        2
+                    }
+                    _ => return None,
+                };
+                Some((specificity, *quality))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, quality)| quality);
+
+        if quality > 0.0 && !best.is_some_and(|(_, best_quality)| quality <= best_quality) {
+            best = Some((offered, quality));
+        }
+    }
+    best.map(|(offered, _)| offered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, MediaType};
+
+    #[test]
+    fn keeps_separators_inside_quoted_parameters() {
+        let media_type = MediaType::parse(r#"multipart/form-data; boundary="a;b\";c"; x=1"#);
+        assert_eq!(media_type.essence(), "multipart/form-data");
+        assert_eq!(media_type.param("boundary"), Some(r#"a;b\";c"#));
+        assert_eq!(media_type.param("x"), Some("1"));
+    }
+
+    #[test]
+    fn prefers_the_highest_quality() {
+        let available = ["text/plain", "application/json"];
+        let accept = Some("text/plain;q=0.5, application/json");
+        assert_eq!(negotiate(accept, &available), Some("application/json"));
+        let accept = Some("text/plain;q=0.9, application/json;q=0.8");
+        assert_eq!(negotiate(accept, &available), Some("text/plain"));
+    }
+
+    #[test]
+    fn breaks_ties_by_the_order_offered() {
+        let accept = Some("application/json, text/plain");
+        assert_eq!(negotiate(accept, &["text/plain", "application/json"]), Some("text/plain"));
+        let json_first = ["application/json", "text/plain"];
+        assert_eq!(negotiate(accept, &json_first), Some("application/json"));
+        assert_eq!(negotiate(None, &["text/html", "text/plain"]), Some("text/html"));
+        assert_eq!(negotiate(Some(" "), &["text/html", "text/plain"]), Some("text/html"));
+    }
+
+    #[test]
+    fn excludes_types_of_quality_zero() {
+        assert_eq!(negotiate(Some("text/html;q=0"), &["text/html"]), None);
+        assert_eq!(negotiate(Some("image/png"), &["text/html", "text/plain"]), None);
+        let accept = Some("*/*, text/html;q=0");
+        assert_eq!(negotiate(accept, &["text/html", "text/plain"]), Some("text/plain"));
+    }
+
+    #[test]
+    fn lets_the_most_specific_range_decide() {
+        let available = ["text/html", "text/plain", "application/json"];
+        let accept = Some("*/*;q=0.1, text/*;q=0.5, text/plain");
+        assert_eq!(negotiate(accept, &available), Some("text/plain"));
+        let accept = Some("text/*, TEXT/HTML;q=0.2, */*;q=0.9");
+        assert_eq!(negotiate(accept, &available), Some("text/plain"));
+        let accept = Some("text/*;q=0.3, */*;q=0.9");
+        assert_eq!(negotiate(accept, &available), Some("application/json"));
+    }
+
+    #[test]
+    fn ignores_commas_inside_quoted_parameters() {
+        let accept = Some(r#"text/html;x="a, text/plain";q=0.2, application/json;q=0.4"#);
+        let available = ["text/html", "text/plain", "application/json"];
+        assert_eq!(negotiate(accept, &available), Some("application/json"));
+    }
+}
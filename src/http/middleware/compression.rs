@@ -0,0 +1,124 @@
+use crate::{
+    encoding::Encoding,
+    http::{
+        middleware::{Middleware, Next},
+        request::Request,
+        response::Response,
+    },
+};
+
+/// Compresses response bodies with the supported encoding that `Accept-Encoding` prefers
+pub struct Compression {
+    /// Smaller bodies are sent as they are, since compressing them gains little
+    pub min_bytes: usize,
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let accept_encoding = request.header("Accept-Encoding").unwrap_or("");
+        let response_encoding = preferred_encoding(accept_encoding);
+
+        let mut response = next.run(request);
+        response.append_header("Vary", "Accept-Encoding");
+        if let Some(encoding) = response_encoding.filter(|_| response.body_len() >= self.min_bytes) {
+            response.encode_body(encoding);
+        }
+        response
+    }
+}
+
+const SUPPORTED: [Encoding; 1] = [Encoding::Gzip];
+
+/// The supported encoding with the highest q-value, the first listed of those on a tie. An
+/// encoding that isn't listed gets the q-value of `*`, if that is listed. A q-value of 0 forbids.
+fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let codings: Vec<(&str, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next()?.trim();
+            let quality = match params
+                .filter_map(|param| param.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            {
+                // a coding with a malformed q-value is ignored
+                Some((_, quality)) => quality.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            Some((name, quality)).filter(|_| !name.is_empty())
+        })
+        .collect();
+    let quality = |name: &str| {
+        codings
+            .iter()
+            .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+            .or_else(|| codings.iter().find(|(coding, _)| *coding == "*"))
+            .map_or(0.0, |(_, quality)| *quality)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in SUPPORTED {
+        let quality = quality(encoding.into());
+        if quality > 0.0 && !best.is_some_and(|(_, best_quality)| quality <= best_quality) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{preferred_encoding, Compression};
+    use crate::{
+        encoding::Encoding,
+        http::{
+            middleware::{tests::request, Chain},
+            response::{success, Response},
+        },
+    };
+
+    fn compressed(accept_encoding: &str, body_len: usize) -> Response {
+        let chain = Chain::default().with(Compression { min_bytes: 10 });
+        let request = request("GET", "/", &[("Accept-Encoding", accept_encoding)]);
+        chain.handle(request, &|_| success::plain_text("a".repeat(body_len)))
+    }
+
+    fn content_encoding(response: Response) -> Option<String> {
+        let (_, headers, _) = response.into_parts();
+        headers
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Content-Encoding"))
+            .map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn honours_q_values() {
+        assert!(matches!(preferred_encoding("gzip"), Some(Encoding::Gzip)));
+        assert!(matches!(preferred_encoding("br, GZIP;q=0.5"), Some(Encoding::Gzip)));
+        assert!(matches!(preferred_encoding("*"), Some(Encoding::Gzip)));
+        assert!(matches!(preferred_encoding("*;q=0.1, gzip;q=1"), Some(Encoding::Gzip)));
+        assert!(preferred_encoding("").is_none());
+        assert!(preferred_encoding("br, deflate").is_none());
+        assert!(preferred_encoding("gzip;q=0").is_none());
+        assert!(preferred_encoding("gzip; q=0.000").is_none());
+        assert!(preferred_encoding("*, gzip;q=0").is_none());
+        assert!(preferred_encoding("gzip;q=high").is_none());
+    }
+
+    #[test]
+    fn compresses_large_enough_bodies() {
+        let response = compressed("gzip", 100);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert!(response.body_len() < 100);
+        assert_eq!(content_encoding(response).as_deref(), Some("gzip"));
+
+        let response = compressed("gzip", 9);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(content_encoding(response), None);
+
+        let response = compressed("gzip;q=0, identity", 100);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body_len(), 100);
+        assert_eq!(content_encoding(response), None);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::http::{
+    middleware::{Middleware, Next},
+    request::Request,
+    response::{success, Response},
+    Method,
+};
+
+/// Cross-Origin Resource Sharing: answers preflight requests and adds
+/// `Access-Control-Allow-Origin` to responses for allowed origins
+pub struct Cors {
+    /// `None` allows any origin
+    allowed_origins: Option<Vec<Box<str>>>,
+    allowed_methods: Box<str>,
+    allowed_headers: Box<str>,
+    max_age_secs: u32,
+}
+
+impl Cors {
+    pub fn any_origin() -> Self {
+        Cors {
+            allowed_origins: None,
+            ..Default::default()
+        }
+    }
+
+    pub fn origins(origins: impl IntoIterator<Item = impl Into<Box<str>>>) -> Self {
+        Cors {
+            allowed_origins: Some(origins.into_iter().map(Into::into).collect()),
+            ..Default::default()
+        }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.allowed_origins {
+            None => true,
+            Some(origins) => origins.iter().any(|o| o.as_ref() == origin),
+        }
+    }
+
+    /// Unless any origin is allowed, the headers of every response depend on `Origin`, even
+    /// those without any, which caches mustn't hand to an allowed origin
+    fn vary(&self, response: &mut Response) {
+        if self.allowed_origins.is_some() {
+            response.append_header("Vary", "Origin");
+        }
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for an allowed origin
+    fn allow_origin<'a>(&self, origin: &'a str) -> &'a str {
+        if self.allowed_origins.is_none() {
+            "*"
+        } else {
+            origin
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            allowed_origins: Some(Vec::new()),
+            allowed_methods: "GET, POST, OPTIONS".into(),
+            allowed_headers: "Content-Type, Accept, Accept-Encoding".into(),
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let Some(origin) = request.header("Origin").filter(|o| self.allows(o)) else {
+            let mut response = next.run(request);
+            self.vary(&mut response);
+            return response;
+        };
+        let allow_origin = self.allow_origin(origin).to_string();
+
+        let is_preflight = *request.method() == Method::Options
+            && request.header("Access-Control-Request-Method").is_some();
+
+        let mut response = if is_preflight {
+            let mut response = success::no_content();
+            response.add_header("Access-Control-Allow-Methods", &self.allowed_methods);
+            response.add_header("Access-Control-Allow-Headers", &self.allowed_headers);
+            response.add_header("Access-Control-Max-Age", &self.max_age_secs.to_string());
+            response
+        } else {
+            next.run(request)
+        };
+
+        response.add_header("Access-Control-Allow-Origin", &allow_origin);
+        self.vary(&mut response);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cors;
+    use crate::http::{
+        middleware::{tests::request, Chain},
+        response::{success, Response},
+    };
+
+    fn respond(cors: Cors, method: &str, headers: &[(&str, &str)]) -> Response {
+        let chain = Chain::default().with(cors);
+        chain.handle(request(method, "/", headers), &|_| {
+            success::plain_text("endpoint".to_string())
+        })
+    }
+
+    #[test]
+    fn allows_listed_origins() {
+        let cors = || Cors::origins(["https://a.example"]);
+        let response = respond(cors(), "GET", &[("Origin", "https://a.example")]);
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://a.example"));
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        for headers in [&[("Origin", "https://b.example")][..], &[]] {
+            let response = respond(cors(), "GET", headers);
+            assert_eq!(response.status().code(), 200);
+            assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+            assert_eq!(response.header("Vary"), Some("Origin"));
+        }
+    }
+
+    #[test]
+    fn allows_any_origin_without_varying() {
+        let response = respond(Cors::any_origin(), "GET", &[("Origin", "https://a.example")]);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Vary"), None);
+
+        let response = respond(Cors::any_origin(), "GET", &[]);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn answers_preflight_requests() {
+        let headers = [
+            ("Origin", "https://a.example"),
+            ("Access-Control-Request-Method", "POST"),
+        ];
+        let response = respond(Cors::origins(["https://a.example"]), "OPTIONS", &headers);
+        assert_eq!(response.status().code(), 204);
+        assert_eq!(response.header("Access-Control-Allow-Methods"), Some("GET, POST, OPTIONS"));
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://a.example"));
+
+        // a preflight from an origin that isn't allowed reaches the endpoint
+        let headers = [
+            ("Origin", "https://b.example"),
+            ("Access-Control-Request-Method", "POST"),
+        ];
+        let response = respond(Cors::origins(["https://a.example"]), "OPTIONS", &headers);
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::http::{
+    middleware::{Middleware, Next},
+    request::Request,
+    response::Response,
+};
+use std::time::Instant;
+
+/// Logs every request with the status it was answered with and how long that took
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let summary = request.summary();
+        let start = Instant::now();
+        let response = next.run(request);
+        log::info!(
+            "{summary} -> {} in {:?}",
+            response.status().code(),
+            start.elapsed()
+        );
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Logging;
+    use crate::http::{
+        middleware::{tests::request, Chain},
+        response::{client_error, success},
+    };
+
+    #[test]
+    fn passes_requests_and_responses_through() {
+        let chain = Chain::default().with(Logging);
+        let response = chain.handle(request("GET", "/a", &[]), &|request| {
+            success::plain_text(request.summary())
+        });
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(response.body_len(), "GET /a".len());
+
+        let response = chain.handle(request("GET", "/b", &[]), &|_| client_error::not_found());
+        assert_eq!(response.status().code(), 404);
+    }
+}
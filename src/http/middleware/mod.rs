@@ -0,0 +1,137 @@
+use crate::http::{request::Request, response::Response};
+
+mod compression;
+mod cors;
+mod logging;
+
+pub use compression::Compression;
+pub use cors::Cors;
+pub use logging::Logging;
+
+/// Behaviour wrapped around request handling.
+///
+/// A middleware receives the request before it is dispatched and may inspect or modify it. It
+/// then either passes it on with [`Next::run`], getting back the response to inspect or modify,
+/// or short-circuits by returning a response of its own.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response;
+}
+
+/// The rest of the chain after the current middleware
+pub struct Next<'a> {
+    rest: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Fn(Request) -> Response,
+}
+
+impl Next<'_> {
+    pub fn run(self, request: Request) -> Response {
+        match self.rest.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+/// Middleware applied in the order they were added: the first sees the request first and the
+/// response last.
+#[derive(Default)]
+pub struct Chain {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn handle(&self, request: Request, endpoint: &dyn Fn(Request) -> Response) -> Response {
+        Next {
+            rest: &self.middleware,
+            endpoint,
+        }
+        .run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chain, Middleware, Next};
+    use crate::http::{
+        request::Request,
+        response::{success, Response},
+        Version,
+    };
+    use std::collections::HashMap;
+
+    pub(super) fn request(method: &str, target: &str, headers: &[(&str, &str)]) -> Request {
+        let headers: HashMap<Box<str>, Box<str>> =
+            headers.iter().map(|&(key, value)| (key.into(), value.into())).collect();
+        Request::from_parts(method, target, Version::Ver1_1, headers, Box::new([]), 0).unwrap()
+    }
+
+    /// Appends its name to `X-Trail` on the way in and out, or answers itself if `stops`
+    struct Trail {
+        name: &'static str,
+        stops: bool,
+    }
+
+    impl Middleware for Trail {
+        fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+            let trail = format!("{} >{}", request.header("X-Trail").unwrap_or(""), self.name);
+            request.set_header("X-Trail", &trail);
+            let (mut response, trail) = if self.stops {
+                (success::no_content(), trail)
+            } else {
+                let response = next.run(request);
+                let trail = response.header("X-Trail").unwrap_or("").to_string();
+                (response, trail)
+            };
+            response.add_header("X-Trail", &format!("{trail} <{}", self.name));
+            response
+        }
+    }
+
+    fn trail(name: &'static str) -> Trail {
+        Trail { name, stops: false }
+    }
+
+    fn endpoint(request: Request) -> Response {
+        let mut response = success::plain_text(String::new());
+        let trail = format!("{} endpoint", request.header("X-Trail").unwrap_or(""));
+        response.add_header("X-Trail", &trail);
+        response
+    }
+
+    #[test]
+    fn runs_in_the_order_added() {
+        let chain = Chain::default().with(trail("a")).with(trail("b"));
+        let response = chain.handle(request("GET", "/", &[]), &endpoint);
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(response.header("X-Trail"), Some(" >a >b endpoint <b <a"));
+    }
+
+    #[test]
+    fn stops_where_a_middleware_answers_itself() {
+        let stop = Trail {
+            name: "b",
+            stops: true,
+        };
+        let chain = Chain::default().with(trail("a")).with(stop).with(trail("c"));
+        let response = chain.handle(request("GET", "/", &[]), &endpoint);
+        assert_eq!(response.status().code(), 204);
+        assert_eq!(response.header("X-Trail"), Some(" >a >b <b <a"));
+    }
+
+    #[test]
+    fn calls_the_endpoint_without_middleware() {
+        let response = Chain::default().handle(request("GET", "/", &[]), &endpoint);
+        assert_eq!(response.header("X-Trail"), Some(" endpoint"));
+    }
+}
@@ -1,20 +1,42 @@
 use crate::http::error::BadRequest;
 use crate::http::response::{Response, CRLF};
-use std::io::{self, Write};
-use std::net::TcpStream;
+use crate::http::connection::Connection;
+use std::io::{self, BufWriter, Write};
 
+pub mod body;
+pub mod connection;
 pub mod error;
+pub mod error_page;
+pub mod h2;
+pub mod media_type;
+pub mod middleware;
+pub mod multipart;
 pub mod request;
 pub mod response;
+pub mod sse;
+pub mod static_files;
+pub mod status;
+pub mod websocket;
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Method {
     Get,
     Post,
+    Options,
     // Put,
     // ...
 }
 
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Options => "OPTIONS",
+        }
+    }
+}
+
 impl TryFrom<&'_ str> for Method {
     type Error = BadRequest;
 
@@ -22,6 +44,7 @@ impl TryFrom<&'_ str> for Method {
         match value {
             "GET" => Ok(Self::Get),
             "POST" => Ok(Self::Post),
+            "OPTIONS" => Ok(Self::Options),
             // "PUT" => Ok(Self::Put),
             other => {
                 log::error!("received unrecognised method: \"{other}\"");
@@ -31,11 +54,13 @@ impl TryFrom<&'_ str> for Method {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Version {
     // ..
+    Ver1_0,
     Ver1_1,
-    Ver2_0,
+    /// Never parsed from a request line: HTTP/2 requests arrive as frames, see [`h2`]
+    Ver2,
     // ..
 }
 
@@ -47,9 +72,14 @@ impl Version {
 
 impl Version {
     fn serialize(self) -> &'static [u8] {
+        self.as_str().as_bytes()
+    }
+
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Self::Ver1_1 => b"HTTP/1.1",
-            Self::Ver2_0 => b"HTTP/2.0",
+            Self::Ver1_0 => "HTTP/1.0",
+            Self::Ver1_1 => "HTTP/1.1",
+            Self::Ver2 => "HTTP/2",
         }
     }
 }
@@ -59,8 +89,8 @@ impl<'a> TryFrom<&'a str> for Version {
 
     fn try_from(value: &'a str) -> Result<Self, BadRequest> {
         match value {
+            "HTTP/1.0" => Ok(Self::Ver1_0),
             "HTTP/1.1" => Ok(Self::Ver1_1),
-            "HTTP/2.0" => Ok(Self::Ver2_0),
             _other => Err(BadRequest::UnsupportedHTTPVersion),
         }
     }
@@ -88,14 +118,51 @@ impl TryFrom<Vec<u8>> for Header {
 
 pub const READING_MEMORY: &str = "Reading a slice is infallable";
 
+/// How much of a response was written
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BytesSent {
+    /// Everything written to the connection, head and framing included
+    pub total: u64,
+    /// The body alone
+    pub body: u64,
+}
+
 pub trait HTTPCarrier {
-    fn respond(&mut self, response: Response) -> io::Result<()>;
+    fn respond(&mut self, response: Response) -> io::Result<BytesSent>;
+}
+
+impl<R, W: Write> HTTPCarrier for Connection<R, W> {
+    fn respond(&mut self, response: Response) -> io::Result<BytesSent> {
+        let counter = CountingWriter {
+            inner: &mut self.writer,
+            count: 0,
+        };
+        // buffered, so that a TLS stream doesn't seal every header into a record of its own
+        let mut buffered = BufWriter::new(counter);
+        let body = response.write_to(&mut buffered)?;
+        buffered.flush()?;
+        let counter = buffered.into_inner().map_err(io::IntoInnerError::into_error)?;
+        Ok(BytesSent {
+            total: counter.count,
+            body,
+        })
+    }
 }
 
-impl HTTPCarrier for TcpStream {
-    fn respond(&mut self, response: Response) -> io::Result<()> {
-        response.write_to(&mut *self)?;
-        self.flush()
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
 
@@ -0,0 +1,277 @@
+use crate::http::{
+    error::{BadRequest, MultipartError},
+    media_type::MediaType,
+    Header,
+};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+/// Upper bound on the size of a single part's header section
+const MAX_PART_HEADERS_LEN: usize = 8 * 1024;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A streaming parser over a `multipart/form-data` body (RFC 7578).
+///
+/// Parts are visited in order with [`Multipart::next_part`]. The body of the current part can be
+/// streamed out with [`Multipart::read_body`]; if it isn't, it is skipped by the next call to
+/// `next_part`.
+pub struct Multipart<R> {
+    reader: R,
+    /// `"\r\n--"` followed by the boundary
+    delimiter: Box<[u8]>,
+    buf: Vec<u8>,
+    finished: bool,
+}
+
+/// The headers of a single part of a multipart body
+#[derive(Debug)]
+pub struct Part {
+    headers: HashMap<Box<str>, Box<str>>,
+}
+
+impl Part {
+    /// Header keys are matched case-insensitively
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    /// The `name` parameter of the `Content-Disposition` header
+    pub fn name(&self) -> Option<&str> {
+        MediaType::parse(self.header("Content-Disposition")?).param("name")
+    }
+
+    /// The `filename` parameter of the `Content-Disposition` header. Only file parts have one.
+    pub fn filename(&self) -> Option<&str> {
+        MediaType::parse(self.header("Content-Disposition")?).param("filename")
+    }
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Self {
+        // The first delimiter isn't required to be preceded by a CRLF. Pretending that one was
+        // read lets every delimiter be matched the same way.
+        let buf = b"\r\n".to_vec();
+        let delimiter = [b"\r\n--", boundary.as_bytes()].concat().into_boxed_slice();
+        Multipart {
+            reader,
+            delimiter,
+            buf,
+            finished: false,
+        }
+    }
+
+    /// Creates a parser from the value of a `Content-Type` header
+    ///
+    /// # Errors
+    /// Errors if the media type isn't `multipart/form-data` or has no boundary
+    pub fn from_content_type(reader: R, content_type: &str) -> Result<Self, BadRequest> {
+        let media_type = MediaType::parse(content_type);
+        if media_type.essence() != "multipart/form-data" {
+            return Err(BadRequest::UnsupportedMediaType(media_type.essence().into()));
+        }
+        let boundary = media_type
+            .param("boundary")
+            .filter(|b| !b.is_empty())
+            .ok_or(BadRequest::MissingBoundary)?;
+        Ok(Self::new(reader, boundary))
+    }
+
+    /// Advances to the next part, skipping whatever remains of the current one.
+    /// Returns `None` once the closing delimiter has been read.
+    pub fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        self.read_body(io::sink())?;
+        self.buf.drain(..self.delimiter.len());
+
+        self.fill_to(2)?;
+        if self.buf.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        if !self.buf.starts_with(b"\r\n") {
+            return Err(BadRequest::MalformedMultipart("delimiter not followed by CRLF").into());
+        }
+        self.buf.drain(..2);
+
+        let mut headers = HashMap::new();
+        let mut headers_len = 0;
+        loop {
+            let line = self.take_line()?;
+            if line.is_empty() {
+                break; // end of part headers
+            }
+            headers_len += line.len();
+            if headers_len > MAX_PART_HEADERS_LEN {
+                return Err(BadRequest::MalformedMultipart("part headers too long").into());
+            }
+            let Header { key, value } = Header::try_from(line)?;
+            headers.insert(key, value);
+        }
+
+        Ok(Some(Part { headers }))
+    }
+
+    /// Streams the body of the current part into `out`, returning the number of bytes written
+    pub fn read_body(&mut self, mut out: impl Write) -> Result<u64, MultipartError> {
+        let mut written = 0;
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                out.write_all(&self.buf[..pos])?;
+                self.buf.drain(..pos);
+                return Ok(written + pos as u64);
+            }
+
+            // Hold back enough bytes to recognise a delimiter split across reads
+            let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+            out.write_all(&self.buf[..safe])?;
+            self.buf.drain(..safe);
+            written += safe as u64;
+
+            if self.fill()? == 0 {
+                return Err(BadRequest::MalformedMultipart("missing closing delimiter").into());
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = find(&self.buf[searched..], b"\r\n") {
+                let end = searched + pos;
+                let line = self.buf[..end].to_vec();
+                self.buf.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_PART_HEADERS_LEN {
+                return Err(BadRequest::MalformedMultipart("part headers too long").into());
+            }
+            searched = self.buf.len().saturating_sub(1);
+            if self.fill()? == 0 {
+                return Err(BadRequest::MalformedMultipart("unterminated part header").into());
+            }
+        }
+    }
+
+    fn fill_to(&mut self, len: usize) -> Result<(), MultipartError> {
+        while self.buf.len() < len {
+            if self.fill()? == 0 {
+                return Err(BadRequest::MalformedMultipart("unexpected end of body").into());
+            }
+        }
+        Ok(())
+    }
+
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let count = loop {
+            match self.reader.read(&mut chunk) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                other => break other?,
+            }
+        };
+        self.buf.extend_from_slice(&chunk[..count]);
+        Ok(count)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Multipart, MAX_PART_HEADERS_LEN};
+    use crate::http::error::{BadRequest, MultipartError};
+    use std::io::{self, Read};
+
+    const BODY: &[u8] = b"--b0undary\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        first\r\n\
+        --b0undary\r\n\
+        Content-Disposition: form-data; name=\"b\"; filename=\"x;y.txt\"\r\n\r\n\
+        second\r\n--b0und\r\nary\r\n\
+        --b0undary--\r\n";
+
+    /// Hands out at most `chunk` bytes per read
+    struct Trickle<'a> {
+        body: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.chunk.min(buf.len()).min(self.body.len());
+            buf[..len].copy_from_slice(&self.body[..len]);
+            self.body = &self.body[len..];
+            Ok(len)
+        }
+    }
+
+    /// The name and body of every part, or why the body is malformed
+    fn parse(body: &[u8], chunk: usize) -> Result<Vec<(String, Vec<u8>)>, &'static str> {
+        let malformed = |error| match error {
+            MultipartError::BadRequest(BadRequest::MalformedMultipart(reason)) => reason,
+            other => panic!("unexpected error: {other}"),
+        };
+        let mut multipart = Multipart::new(Trickle { body, chunk }, "b0undary");
+        let mut parts = Vec::new();
+        while let Some(part) = multipart.next_part().map_err(malformed)? {
+            let name = part.name().unwrap_or_default().to_string();
+            let mut body = Vec::new();
+            multipart.read_body(&mut body).map_err(malformed)?;
+            parts.push((name, body));
+        }
+        Ok(parts)
+    }
+
+    fn expected() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("a".into(), b"first".to_vec()),
+            ("b".into(), b"second\r\n--b0und\r\nary".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn finds_delimiters_split_across_reads() {
+        for chunk in 1..=BODY.len() {
+            assert_eq!(parse(BODY, chunk), Ok(expected()), "reading {chunk} bytes at a time");
+        }
+    }
+
+    #[test]
+    fn ignores_the_preamble_and_epilogue() {
+        let body = [b"a preamble\r\n", BODY, b"an epilogue\r\n--b0undary\r\n"].concat();
+        assert_eq!(parse(&body, body.len()), Ok(expected()));
+    }
+
+    #[test]
+    fn reads_quoted_parameters_of_parts() {
+        let mut multipart = Multipart::new(BODY, "b0undary");
+        multipart.next_part().unwrap();
+        let part = multipart.next_part().unwrap().expect("a second part");
+        assert_eq!(part.filename(), Some("x;y.txt"));
+    }
+
+    #[test]
+    fn rejects_a_missing_closing_delimiter() {
+        let body = &BODY[..BODY.len() - b"--b0undary--\r\n".len()];
+        assert_eq!(parse(body, body.len()), Err("missing closing delimiter"));
+    }
+
+    #[test]
+    fn rejects_oversized_part_headers() {
+        let header = format!("X-Padding: {}\r\n", "x".repeat(MAX_PART_HEADERS_LEN));
+        let body = [b"--b0undary\r\n", header.as_bytes(), &BODY[b"--b0undary\r\n".len()..]];
+        let body = body.concat();
+        assert_eq!(parse(&body, 1024), Err("part headers too long"));
+    }
+}
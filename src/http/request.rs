@@ -1,27 +1,45 @@
 use crate::{
-    encoding::{self, Encoding},
     http::{
+        body::{Form, FromBody, Json},
+        connection::{Connection, Peer},
         error::{BadRequest, InvalidTargetError},
-        response::{client_error, server_error, success, Response, CRLF},
+        error_page::ErrorRendering,
+        media_type::{self, MediaType},
+        multipart::Multipart,
+        response::{client_error, server_error, success, Response, ResponseStatus, CRLF},
+        sse,
+        static_files::Mounts,
+        websocket,
         Header, Method, Version,
     },
-    DIRECTORY,
 };
+use serde_json::json;
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fmt::{self, Formatter},
     fs::{self, File},
-    io::{self, BufRead, BufReader, Read},
-    net::TcpStream,
-    path::{Path, PathBuf},
+    io::{self, BufRead, Read},
+    path::Path,
 };
 
+/// What handlers need to know about the server, passed to them rather than kept in statics
+#[derive(Debug, Default)]
+pub struct AppState {
+    pub mounts: Mounts,
+    pub errors: ErrorRendering,
+}
+
 pub struct Request {
     method: Method,
     target: Target,
     http_version: Version,
     headers: HashMap<Box<str>, Box<str>>,
     body: Box<[u8]>,
+    /// Bytes read off the connection for this request, including the request line and headers
+    wire_size: usize,
+    /// Set by the server once the request is read
+    peer: Option<Peer>,
 }
 
 impl fmt::Debug for Request {
@@ -32,6 +50,8 @@ impl fmt::Debug for Request {
             http_version,
             headers,
             body,
+            wire_size: _,
+            peer: _,
         } = self;
         write!(
             f,
@@ -48,171 +68,523 @@ impl fmt::Debug for Request {
 }
 
 impl Request {
-    pub fn handle(self) -> Response {
-        log::trace!("received {self:?}");
-        let Request {
-            target: Target { path_str },
+    /// A request that arrived in some other form than HTTP/1 text, e.g. as an HTTP/2 stream
+    pub(crate) fn from_parts(
+        method: &str,
+        target: &str,
+        http_version: Version,
+        headers: HashMap<Box<str>, Box<str>>,
+        body: Box<[u8]>,
+        wire_size: usize,
+    ) -> Result<Request, BadRequest> {
+        // only the origin-form is allowed, and `*` for OPTIONS (RFC 9113, section 8.3.1)
+        let valid = target.starts_with('/') || (target == "*" && method == "OPTIONS");
+        match target {
+            "" => return Err(BadRequest::MissingTarget),
+            _ if !valid => return Err(InvalidTargetError::DoesNotStartWithSlash.into()),
+            _ => {}
+        }
+        Ok(Request {
+            method: method.try_into()?,
+            target: target.try_into()?,
+            http_version,
+            headers,
             body,
-            method,
-            mut headers,
-            ..
-        } = self;
+            wire_size,
+            peer: None,
+        })
+    }
 
-        let (endpoint, remainder) = path_str.split_once('/').unwrap_or((&path_str, ""));
-
-        let encoding_options_str = headers.remove("Accept-Encoding").unwrap_or("".into());
-        let response_encoding = get_first_supported_encoding(&encoding_options_str);
-        dbg!(&response_encoding);
-
-        let result = match (method, endpoint) {
-            (Method::Get, "") => Ok(Response::default()),
-            (Method::Get, "files") => handle_get_file(remainder, response_encoding),
-            (Method::Post, "files") => handle_post_file(remainder, body),
-            (Method::Get, "echo") => Ok(success::plain_text(
-                remainder.to_string(),
-                response_encoding,
-            )),
-            (Method::Get, "user-agent") if remainder.is_empty() => {
-                handle_get_user_agent(&mut headers)
-            }
-            (Method::Get, other) => {
-                log::debug!("request to unimplemented endpoint: {other}");
-                Err(client_error::not_found())
-            }
-            (Method::Post, _) => todo!("handle invalid post targets"),
+    /// A short description for logs, e.g. `GET /echo/abc`
+    pub fn summary(&self) -> String {
+        format!("{} {}", self.method.as_str(), self.target())
+    }
+
+    pub fn wire_size(&self) -> usize {
+        self.wire_size
+    }
+
+    /// The mount or endpoint the request is routed to, as a label with bounded cardinality
+    pub fn route(&self, state: &AppState) -> Cow<'static, str> {
+        if let Some(route) = state.mounts.route(&self.target()) {
+            return route.into();
+        }
+        let path_str = &self.target.path_str;
+        let endpoint = path_str.split_once('/').map_or(path_str.as_str(), |(e, _)| e);
+        let route = match endpoint {
+            "" => "/",
+            "upload" => "/upload",
+            "echo" => "/echo/*",
+            "user-agent" => "/user-agent",
+            "ws" => "/ws/echo",
+            "events" => "/events/counter",
+            _ => "unmatched",
         };
+        route.into()
+    }
+
+    /// Who sent the request, e.g. to log the uid of a local process
+    pub fn peer(&self) -> Option<&Peer> {
+        self.peer.as_ref()
+    }
+
+    pub fn set_peer(&mut self, peer: Peer) {
+        self.peer = Some(peer);
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The path of the target, including the leading `/`, or `*` for the server as a whole
+    pub fn target(&self) -> String {
+        match self.target.asterisk {
+            true => "*".to_string(),
+            false => format!("/{}", self.target.path_str),
+        }
+    }
+
+    /// The target as the client sent it, query included
+    pub fn request_target(&self) -> String {
+        match &self.target.query {
+            Some(query) => format!("{}?{query}", self.target()),
+            None => self.target(),
+        }
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.http_version
+    }
+
+    /// Whether the client wants the connection kept open after the response (RFC 9112 §9.3):
+    /// HTTP/1.0 only when asking with `Connection: keep-alive`, later versions unless asking
+    /// otherwise with `Connection: close`
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|o| o.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        match self.http_version {
+            Version::Ver1_0 => has_option("keep-alive") && !has_option("close"),
+            Version::Ver1_1 | Version::Ver2 => !has_option("close"),
+        }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Header names are matched case-insensitively
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.remove_header(key);
+        self.headers.insert(key.into(), value.into());
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<Box<str>> {
+        let key = self.headers.keys().find(|k| k.eq_ignore_ascii_case(key))?.clone();
+        self.headers.remove(&key)
+    }
 
-        let mut result = result.unwrap_or_else(Response::from);
+    pub fn handle(self, state: &AppState) -> Response {
+        log::trace!("received {self:?}");
+        let result = match state.mounts.handle(&self) {
+            Some(result) => result,
+            None => self.handle_endpoint(state),
+        };
+        result.unwrap_or_else(|mut err_response| {
+            let request_id = self.header("X-Request-Id").unwrap_or("-");
+            let accept = self.header("Accept");
+            err_response.render_error(accept, &self.target(), request_id, &state.errors);
+            err_response
+        })
+    }
 
-        if headers
-            .remove("Connection")
-            .is_some_and(|val| val.as_ref() == "close")
-        {
-            result.add_header("Connection", "close");
+    /// Whether [`Request::handle`] would take the body of a request, judging by its head alone
+    pub fn admit(&self, state: &AppState) -> Result<(), Response> {
+        if let Some(result) = state.mounts.admit(self) {
+            return result;
         }
+        match Endpoint::of(self) {
+            Some(_) => Ok(()),
+            None => Err(client_error::not_found()),
+        }
+    }
 
-        result
+    fn handle_endpoint(&self, state: &AppState) -> Result<Response, Response> {
+        let Some(endpoint) = Endpoint::of(self) else {
+            log::debug!("request to unimplemented endpoint: {}", self.summary());
+            return Err(client_error::not_found());
+        };
+        match endpoint {
+            Endpoint::Index => Ok(Response::default()),
+            Endpoint::Upload => handle_post_upload(state, self),
+            Endpoint::PostEcho => handle_post_echo(self),
+            Endpoint::GetEcho(text) => text_or_json(self.header("Accept"), "echo", text.into()),
+            Endpoint::UserAgent => handle_get_user_agent(self),
+            Endpoint::WebSocketEcho => websocket::accept(self, websocket::echo),
+            Endpoint::EventCounter => Ok(sse::counter(self)),
+            Endpoint::Options => {
+                let mut response = success::no_content();
+                response.add_header("Allow", "GET, POST, OPTIONS");
+                Ok(response)
+            }
+        }
     }
 }
 
-fn handle_post_file(file_path: &str, content: Box<[u8]>) -> Result<Response, Response> {
-    let path = try_create_path(file_path)?;
-    write_creating_parents(path, content)?;
+/// The built-in endpoints, which both [`Request::handle`] and [`Request::admit`] route by
+enum Endpoint<'a> {
+    Index,
+    Upload,
+    PostEcho,
+    /// Along with the text to echo
+    GetEcho(&'a str),
+    UserAgent,
+    WebSocketEcho,
+    EventCounter,
+    Options,
+}
 
-    Ok(success::created())
+impl Endpoint<'_> {
+    fn of(request: &Request) -> Option<Endpoint<'_>> {
+        let path_str = request.target.path_str.as_str();
+        let (endpoint, remainder) = path_str.split_once('/').unwrap_or((path_str, ""));
+        let endpoint = match (&request.method, endpoint) {
+            (Method::Get, "") => Endpoint::Index,
+            (Method::Post, "upload") if remainder.is_empty() => Endpoint::Upload,
+            (Method::Post, "echo") if remainder.is_empty() => Endpoint::PostEcho,
+            (Method::Get, "echo") => Endpoint::GetEcho(remainder),
+            (Method::Get, "user-agent") if remainder.is_empty() => Endpoint::UserAgent,
+            // the handshake checks the method itself, to answer with the right error
+            (_, "ws") if remainder == "echo" => Endpoint::WebSocketEcho,
+            (Method::Get, "events") if remainder == "counter" => Endpoint::EventCounter,
+            (Method::Options, _) => Endpoint::Options,
+            _ => return None,
+        };
+        Some(endpoint)
+    }
 }
 
-fn handle_get_file(file_path: &str, opt_encoding: Option<Encoding>) -> Result<Response, Response> {
-    log::debug!("retreiving file...");
-    let path = try_create_path(file_path)?;
+/// Echoes a url-encoded form or JSON body back as plain text once parsed
+fn handle_post_echo(request: &Request) -> Result<Response, Response> {
+    let content_type = request.header("Content-Type");
+    let is_json = content_type.is_some_and(|ct| Json::accepts(MediaType::parse(ct).essence()));
 
-    let mut file = File::open(path)?;
-    let body = if let Some(encoding) = opt_encoding {
-        encoding::read_and_encode(file, encoding)?
+    let text = if is_json {
+        let Json(value) = Json::from_body(content_type, request.body())?;
+        value.to_string()
     } else {
-        let mut vec = Vec::new();
-        file.read_to_end(&mut vec)?;
-        vec
+        let form = Form::from_body(content_type, request.body())?;
+        form.iter().map(|(key, value)| format!("{key}={value}\n")).collect()
     };
-
-    Ok(success::octet_stream(body, opt_encoding))
+    Ok(success::plain_text(text))
 }
 
-fn get_first_supported_encoding(supported_encodings_str: &str) -> Option<Encoding> {
-    dbg!(supported_encodings_str);
-    supported_encodings_str
-        .split(',')
-        .map(str::trim_ascii_start)
-        .find_map(|str| Encoding::try_from(str).ok())
+/// Stores every file part of a `multipart/form-data` body in the first writable mount
+fn handle_post_upload(state: &AppState, request: &Request) -> Result<Response, Response> {
+    let content_type = request
+        .header("Content-Type")
+        .ok_or(BadRequest::MissingHeader("Content-Type"))?;
+    let mut multipart = Multipart::from_content_type(request.body(), content_type)?;
+    let root = state.mounts.upload_root().ok_or_else(|| {
+        log::error!("no writable mount to store uploads in");
+        server_error::generic()
+    })?;
+
+    while let Some(part) = multipart.next_part()? {
+        let Some(file_name) = part.filename().and_then(|f| Path::new(f).file_name()) else {
+            log::debug!("skipping non-file part {:?}", part.name());
+            continue;
+        };
+        let path = root.join(file_name);
+        log::debug!("storing uploaded file at {path:?}");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        multipart.read_body(File::create(path)?)?;
+    }
+
+    Ok(success::created())
 }
 
-fn handle_get_user_agent(headers: &mut HashMap<Box<str>, Box<str>>) -> Result<Response, Response> {
-    let user_agent = headers
-        .remove("User-Agent")
+fn handle_get_user_agent(request: &Request) -> Result<Response, Response> {
+    let user_agent = request
+        .header("User-Agent")
         .ok_or(BadRequest::MissingHeader("User-Agent"))?;
-    Ok(success::plain_text(user_agent.into_string(), None))
+    text_or_json(request.header("Accept"), "user-agent", user_agent.to_string())
 }
 
-/// On some OS, creating a file won't work unless it's parents already exist
-fn write_creating_parents<P, C>(path: P, contents: C) -> io::Result<()>
-where
-    P: AsRef<Path>,
-    C: AsRef<[u8]>,
-{
-    if let Some(parent) = path.as_ref().parent() {
-        fs::create_dir_all(parent)?;
-    }
-    fs::write(path, contents)
+/// Responds with `text` as plain text, or as a JSON object `{ key: text }` if the client prefers
+fn text_or_json(accept: Option<&str>, key: &str, text: String) -> Result<Response, Response> {
+    let mut result = match media_type::negotiate(accept, &["text/plain", "application/json"]) {
+        Some("application/json") => Ok(success::json(&json!({ key: text }))),
+        Some(_) => Ok(success::plain_text(text)),
+        None => Err(client_error::not_acceptable()),
+    };
+    let (Ok(response) | Err(response)) = &mut result;
+    response.append_header("Vary", "Accept");
+    result
+}
+
+/// Bounds on what a client may send in a single request
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Longer bodies are rejected with 413
+    pub max_body_bytes: usize,
+    /// Longer request lines are rejected with 414, longer header sections with 431
+    pub max_header_bytes: usize,
 }
 
-fn try_create_path(file_name: &str) -> Result<PathBuf, Response> {
-    let path = DIRECTORY
-        .get()
-        .ok_or(server_error::generic())
-        .inspect_err(|_| log::error!("DIRECTORY is not set!"))?
-        .join(file_name);
-    Ok(path)
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_body_bytes: 10 * 1024 * 1024,
+            max_header_bytes: 16 * 1024,
+        }
+    }
 }
+
 pub trait RequestSource {
-    fn read_request(&mut self) -> Result<Request, Option<Response>>;
+    /// Reads the request line and headers, leaving the body unread
+    fn read_head(&mut self, limits: &Limits) -> Result<Head, Option<Response>>;
+
+    fn read_body(&mut self, head: Head) -> Result<Request, Option<Response>>;
+
+    fn read_request(&mut self, limits: &Limits) -> Result<Request, Option<Response>> {
+        let head = self.read_head(limits)?;
+        self.read_body(head)
+    }
 }
 
-impl RequestSource for TcpStream {
-    fn read_request(&mut self) -> Result<Request, Option<Response>> {
-        let mut buf = BufReader::new(self);
+/// A request whose body is still to be read, once its `Content-Length` is known to be within
+/// the limits
+pub struct Head {
+    request: Request,
+    body_len: usize,
+    expects_continue: bool,
+}
+
+impl Head {
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
 
-        let mut sbb = split_by_bytes(&mut buf, CRLF);
-        let request_line = match sbb.next().ok_or(None)? {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                log::error!("{e}");
-                return Err(server_error::generic().into());
+    /// Whether the client waits for `100 Continue` before sending the body (RFC 9110, section
+    /// 10.1.1)
+    pub fn expects_continue(&self) -> bool {
+        self.expects_continue
+    }
+
+    /// What to tell a client that waits before sending the body: `100 Continue` if `admit`
+    /// lets the body come, or else the final response, which closes the connection. `Ok(None)`
+    /// if the client doesn't wait.
+    pub fn interim_response(
+        &self,
+        admit: impl FnOnce(&Request) -> Result<(), Response>,
+    ) -> Result<Option<Response>, Response> {
+        if !self.expects_continue {
+            return Ok(None);
+        }
+        match admit(&self.request) {
+            Ok(()) => Ok(Some(Response::from(ResponseStatus::Continue))),
+            Err(mut response) => {
+                log::debug!("refusing the body of {}", self.request.summary());
+                // the client may send the body anyway, which is not read
+                response.add_header("Connection", "close");
+                Err(response)
             }
-        };
+        }
+    }
 
-        let (method, target, http_version) = parse_request_line(request_line)?;
+    /// The `Content-Length` of the body
+    pub fn body_len(&self) -> usize {
+        self.body_len
+    }
 
-        let mut headers = HashMap::new();
+    pub fn into_request(self, body: Vec<u8>) -> Request {
+        let mut request = self.request;
+        request.wire_size += body.len();
+        request.body = body.into_boxed_slice();
+        log::trace!("parsed request: {request:?}");
+        request
+    }
+}
+
+impl<R: Read, W> RequestSource for Connection<R, W> {
+    fn read_head(&mut self, limits: &Limits) -> Result<Head, Option<Response>> {
+        let mut parser = Parser::default();
+        let mut buf = Vec::new();
         loop {
-            match sbb.next().ok_or(None)? {
-                Ok(bytes) if bytes.is_empty() => break, // found \r\n\r\n (marking end of headers)
-                Ok(bytes) => {
-                    let Header { key, value } = Header::try_from(bytes)?;
-                    headers.insert(key, value);
+            let available = match self.reader.fill_buf() {
+                Ok([]) => return Err(None), // Stream has been closed
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // an idle keep-alive connection timing out is not an error
+                Err(e) if is_timeout(&e) && buf.is_empty() => return Err(None),
+                Err(e) if is_timeout(&e) => return Err(client_error::request_timeout().into()),
+                // e.g. a failed TLS handshake, which leaves nobody to answer
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    log::info!("dropping connection: {e}");
+                    return Err(None);
                 }
-                Err(io_error) => {
-                    log::error!("System error: {io_error}");
+                Err(e) => {
+                    log::error!("{e}");
                     return Err(server_error::generic().into());
                 }
+            };
+            let known = buf.len();
+            buf.extend_from_slice(available);
+            let parsed = parser.parse_head(&buf, limits);
+            // the bytes after the head stay in the reader, for the body or the next request
+            let consumed = match &parsed {
+                Ok(Some((_, len))) => len - known,
+                _ => buf.len() - known,
+            };
+            self.reader.consume(consumed);
+            if let Some((head, _)) = parsed? {
+                return Ok(head);
             }
         }
+    }
 
-        let body = match method {
-            Method::Get => Box::new([]),
-            Method::Post => {
-                let count: usize = headers
-                    .remove("Content-Length")
-                    .ok_or(BadRequest::MissingHeader("Content-Length"))?
-                    .parse::<usize>()
-                    .map_err(|_| BadRequest::HeaderValueParseError {
-                        key: "Content-Length".to_string(),
-                    })?;
-                let mut vec = vec![0; count];
-                buf.read_exact(&mut vec).map_err(|_| None)?;
-                vec.into_boxed_slice()
+    fn read_body(&mut self, head: Head) -> Result<Request, Option<Response>> {
+        let mut body = vec![0; head.body_len];
+        self.reader
+            .read_exact(&mut body)
+            .map_err(|e| is_timeout(&e).then(client_error::request_timeout))?;
+        Ok(head.into_request(body))
+    }
+}
+
+/// Finds the heads of HTTP/1 requests in bytes as they arrive, resuming the search where the
+/// last call left off
+#[derive(Debug, Default)]
+pub struct Parser {
+    /// How far the buffer is known not to hold the end of the head
+    scanned: usize,
+}
+
+impl Parser {
+    /// Parses the head at the start of `buf` once all of it has arrived, returning it along with
+    /// its length. `Ok(None)` waits for more bytes.
+    pub fn parse_head(
+        &mut self,
+        buf: &[u8],
+        limits: &Limits,
+    ) -> Result<Option<(Head, usize)>, Option<Response>> {
+        let window = &buf[..buf.len().min(limits.max_header_bytes)];
+        // the end of the head may straddle what was scanned and what arrived since
+        let from = self.scanned.saturating_sub(HEAD_END.len() - 1);
+        match find(&window[from..], &HEAD_END) {
+            Some(at) => {
+                self.scanned = 0;
+                let len = from + at + HEAD_END.len();
+                Ok(Some((parse_head(&buf[..len], limits)?, len)))
             }
-        };
+            // the limit ran out before the request line did
+            None if window.len() == limits.max_header_bytes && find(window, &CRLF).is_none() => {
+                Err(closing(client_error::uri_too_long()))
+            }
+            None if window.len() == limits.max_header_bytes => {
+                Err(closing(client_error::request_header_fields_too_large()))
+            }
+            None => {
+                self.scanned = window.len();
+                Ok(None)
+            }
+        }
+    }
+}
 
-        let request = Request {
-            method,
-            target,
-            http_version,
-            headers,
-            body,
-        };
-        log::trace!("parsed request: {request:?}");
-        Ok(request)
+/// Ends the header section, and so the head
+const HEAD_END: [u8; 4] = *b"\r\n\r\n";
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Parses a complete head, `HEAD_END` included
+fn parse_head(head: &[u8], limits: &Limits) -> Result<Head, Option<Response>> {
+    let mut lines = head[..head.len() - CRLF.len()]
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let request_line = lines.next().unwrap_or_default();
+    let (method, target, http_version) = parse_request_line(request_line.to_vec())?;
+
+    let mut headers = HashMap::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let Header { key, value } = Header::try_from(line.to_vec())?;
+        headers.insert(key, value);
     }
+
+    let body_len = match method {
+        Method::Get | Method::Options => 0,
+        Method::Post => {
+            let count: usize = headers
+                .remove("Content-Length")
+                .ok_or(BadRequest::MissingHeader("Content-Length"))?
+                .parse::<usize>()
+                .map_err(|_| BadRequest::HeaderValueParseError {
+                    key: "Content-Length".to_string(),
+                })?;
+            if count > limits.max_body_bytes {
+                return Err(closing(client_error::content_too_large()));
+            }
+            count
+        }
+    };
+
+    let expects_continue = match headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Expect"))
+    {
+        None => false,
+        // HTTP/1.0 predates the expectation, so its clients cannot be waiting on it
+        Some(_) if http_version == Version::Ver1_0 => false,
+        Some((_, value)) if value.eq_ignore_ascii_case("100-continue") => body_len > 0,
+        // the body may already be on its way, and is not read
+        Some(_) => return Err(closing(client_error::expectation_failed())),
+    };
+
+    let request = Request {
+        method,
+        target,
+        http_version,
+        headers,
+        body: Box::new([]),
+        wire_size: head.len(),
+        peer: None,
+    };
+    Ok(Head {
+        request,
+        body_len,
+        expects_continue,
+    })
+}
+
+/// For requests rejected before being read in full: what's left of them is still on the
+/// connection, so it can't be reused
+fn closing(mut response: Response) -> Option<Response> {
+    response.add_header("Connection", "close");
+    Some(response)
+}
+
+/// Whether a read failed because the stream's read timeout elapsed
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
 }
 
 fn parse_request_line(request_line: Vec<u8>) -> Result<(Method, Target, Version), BadRequest> {
@@ -243,60 +615,52 @@ fn parse_request_line(request_line: Vec<u8>) -> Result<(Method, Target, Version)
     Ok((method, target, http_version))
 }
 
-/// An iterator over an instance of `BufRead` seperated by a given multi-byte delimiter
-struct SplitByBytes<B, const N: usize> {
-    buf: B,
-    delimiter: [u8; N],
-}
-
-fn split_by_bytes<B: BufRead, const N: usize>(buf: B, delimiter: [u8; N]) -> SplitByBytes<B, N> {
-    SplitByBytes { buf, delimiter }
-}
-
-impl<B: BufRead, const N: usize> Iterator for SplitByBytes<B, N> {
-    type Item = io::Result<Vec<u8>>;
-
-    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
-        let mut vec = Vec::new();
-
-        let last_of_delimiter = self.delimiter[N - 1];
-        loop {
-            match self.buf.read_until(last_of_delimiter, &mut vec) {
-                Ok(0) => return None, // End of reader
-                Err(e) => return Some(Err(e)),
-                Ok(_) if vec.ends_with(&self.delimiter) => {
-                    for _ in 0..N {
-                        vec.pop();
-                    }
-                    return Some(Ok(vec));
-                }
-                Ok(_) => {} // continue
-            }
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct Target {
     path_str: String,
-    // query component(s)
+    /// Everything after the `?`, as sent
+    query: Option<String>,
+    /// The asterisk-form `*`, which asks `OPTIONS` about the server rather than a resource
+    asterisk: bool,
 }
 
 impl TryFrom<&'_ str> for Target {
     type Error = InvalidTargetError;
     fn try_from(str: &str) -> Result<Target, InvalidTargetError> {
-        let (prefix, relevant) = str.split_at(1);
-
-        if prefix != "/" {
-            log::trace!("target deemed invalid: does not start with '/': {str:?}");
-            return Err(InvalidTargetError::DoesNotStartWithSlash);
+        if str == "*" {
+            return Ok(Target {
+                path_str: "*".to_string(),
+                query: None,
+                asterisk: true,
+            });
         }
+        let relevant = match (str.strip_prefix('/'), str.split_once("://")) {
+            (Some(relevant), _) => relevant,
+            // the absolute-form, as sent to proxies: only the path and query are of interest
+            (None, Some((scheme, rest))) => {
+                if !["http", "https"].iter().any(|s| scheme.eq_ignore_ascii_case(s)) {
+                    log::trace!("target deemed invalid: unsupported scheme: {str:?}");
+                    return Err(InvalidTargetError::UnsupportedScheme);
+                }
+                let path = rest.find(['/', '?']).map_or("", |at| &rest[at..]);
+                path.strip_prefix('/').unwrap_or(path)
+            }
+            (None, None) => {
+                log::trace!("target deemed invalid: does not start with '/': {str:?}");
+                return Err(InvalidTargetError::DoesNotStartWithSlash);
+            }
+        };
 
-        let mut split = relevant.split('?'); // todo check the rules of URLs and consider TryFrom instead. This split might not be enough?
+        let mut split = relevant.splitn(2, '?'); // todo check the rules of URLs and consider TryFrom instead. This split might not be enough?
         let path = split.next().unwrap_or("").to_string();
 
-        // todo add query parameters
+        // todo parse query parameters
+        let query = split.next().map(str::to_string);
 
-        Ok(Target { path_str: path })
+        Ok(Target {
+            path_str: path,
+            query,
+            asterisk: false,
+        })
     }
 }
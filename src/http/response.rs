@@ -1,25 +1,237 @@
-use crate::encoding::Encoding;
-use crate::http::response::content_type::ContentType;
-use crate::http::{error, Version, WriteHeader};
-use std::collections::HashMap;
-use std::io::{self, BufWriter, Write};
+use crate::encoding::{read_and_encode, Encoding};
+use crate::http::response::content_type::{Application, ContentType, Text};
+pub use crate::http::status::ResponseStatus;
+use crate::http::{
+    error,
+    error_page::{self, ErrorRendering},
+    media_type, CountingWriter, Version, WriteHeader, READING_MEMORY,
+};
+use serde_json::json;
+use std::io::{self, BufRead, BufWriter, Write};
+
+/// Header names and values, in the order they are sent
+pub(crate) type HeaderList = Vec<(Box<str>, Box<str>)>;
 
 pub struct Response {
     version: Version,
     status: ResponseStatus, // serialization includes code and message
-    dyn_headers: HashMap<Box<str>, Box<str>>,
+    /// A `Vec` rather than a map keeps `Response` small and the headers in insertion order
+    dyn_headers: HeaderList,
     body_data: Option<BodyData>,
+    problem: Option<Box<Problem>>,
+    /// Boxed to keep `Response` small, as it is the error type of every handler
+    takeover: Option<Box<Takeover>>,
+}
+
+/// Takes the connection over once a `101 Switching Protocols` response is sent, reading the
+/// client's bytes from the first argument and writing to the second
+pub type Upgrade = Box<dyn FnOnce(&mut dyn BufRead, &mut dyn Write) + Send>;
+
+/// Writes a body as it is produced, for as long as it takes. The connection is closed once it
+/// returns, which is how the client learns where the body ends.
+pub type BodyStream = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// What keeps a connection from returning to read the next request once the head of a response
+/// is sent
+enum Takeover {
+    Upgrade(Upgrade),
+    Stream(BodyStream),
+}
+
+/// Describes why a request failed (RFC 9457). It becomes the body of the response once the
+/// client's preferred format is known, see [`Response::render_problem`].
+pub struct Problem {
+    /// Stable and machine-readable, e.g. `"missing-header"`
+    kind: &'static str,
+    /// Human-readable and specific to this occurrence. Hidden in production mode.
+    detail: String,
 }
 
 impl Response {
+    /// Replaces any header with the same (case-insensitive) name
     pub(crate) fn add_header(&mut self, p0: &str, p1: &str) {
-        self.dyn_headers.insert(p0.into(), p1.into());
+        match self.dyn_headers.iter_mut().find(|(key, _)| key.eq_ignore_ascii_case(p0)) {
+            Some((_, value)) => *value = p1.into(),
+            None => self.dyn_headers.push((p0.into(), p1.into())),
+        }
+    }
+
+    /// Adds `value` to a comma-separated list header such as `Vary`, unless it's listed already
+    pub(crate) fn append_header(&mut self, key: &str, value: &str) {
+        let listed = |existing: &str| {
+            existing
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(value))
+        };
+        match self.header(key) {
+            Some(existing) if listed(existing) => {}
+            Some(existing) => {
+                let joined = format!("{existing}, {value}");
+                self.add_header(key, &joined);
+            }
+            None => self.add_header(key, value),
+        }
+    }
+
+    pub(crate) fn header(&self, key: &str) -> Option<&str> {
+        self.dyn_headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_ref())
+    }
+}
+
+impl Response {
+    /// Gives the attached problem (if any) a body, as `application/problem+json` or plain text
+    /// depending on `accept`. Responses that already have a body are left unchanged.
+    pub fn render_problem(&mut self, accept: Option<&str>, rendering: &ErrorRendering) {
+        let Some(problem) = self.problem.take() else {
+            return;
+        };
+        if self.body_data.is_some() {
+            return;
+        }
+        let show_detail = !rendering.production;
+        self.append_header("Vary", "Accept");
+
+        // An error is still sent if the client accepts neither format
+        let body_data = match media_type::negotiate(accept, &[PROBLEM_JSON, "text/plain"]) {
+            Some("text/plain") => {
+                let mut text = format!("{} {}", self.status.code(), self.status.reason());
+                if show_detail {
+                    text = format!("{text}: {}", problem.detail);
+                }
+                BodyData {
+                    content_type: ContentType::Text(Text::Plain),
+                    opt_encoding: None,
+                    body: text.into_bytes(),
+                }
+            }
+            _ => {
+                let mut json = json!({
+                    "type": format!("urn:http-server:problem:{}", problem.kind),
+                    "title": self.status.reason(),
+                    "status": self.status.code(),
+                });
+                if show_detail {
+                    json["detail"] = problem.detail.into();
+                }
+                BodyData {
+                    content_type: ContentType::Application(Application::ProblemJson),
+                    opt_encoding: None,
+                    body: json.to_string().into_bytes(),
+                }
+            }
+        };
+        self.body_data = Some(body_data);
+    }
+}
+
+impl Response {
+    /// Gives an error response a body: an HTML error page if the client prefers one, otherwise
+    /// the rendered problem (if any). Responses that already have a body are left unchanged.
+    pub fn render_error(
+        &mut self,
+        accept: Option<&str>,
+        path: &str,
+        request_id: &str,
+        rendering: &ErrorRendering,
+    ) {
+        if self.body_data.is_some() {
+            return;
+        }
+        self.append_header("Vary", "Accept");
+        let wants_html = if self.problem.is_some() {
+            media_type::negotiate(accept, &[PROBLEM_JSON, "text/plain", "text/html"])
+                == Some("text/html")
+        } else {
+            media_type::negotiate(accept, &["text/html"]).is_some()
+        };
+
+        if wants_html {
+            self.problem = None;
+            let pages = rendering.pages.as_deref();
+            let page = error_page::render(&self.status, path, request_id, pages);
+            self.body_data = success::html(page).body_data;
+        } else {
+            self.render_problem(accept, rendering);
+        }
     }
 }
 
+const PROBLEM_JSON: &str = "application/problem+json";
+
 impl Response {
+    pub fn status(&self) -> &ResponseStatus {
+        &self.status
+    }
+
+    pub fn set_status(&mut self, status: ResponseStatus) {
+        self.status = status;
+    }
+
+    pub fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
+    /// Hands the connection to `upgrade` once the response is sent
+    pub fn set_upgrade(&mut self, upgrade: Upgrade) {
+        self.takeover = Some(Box::new(Takeover::Upgrade(upgrade)));
+    }
+
+    /// Whether sending the response hands the connection to an upgrade or a stream
+    pub(crate) fn takes_over(&self) -> bool {
+        self.takeover.is_some()
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        match self.takeover.take().map(|takeover| *takeover) {
+            Some(Takeover::Upgrade(upgrade)) => Some(upgrade),
+            other => {
+                self.takeover = other.map(Box::new);
+                None
+            }
+        }
+    }
+
+    /// Replaces the body with one of `media_type` that `stream` writes after the head is sent.
+    /// HTTP/1 only, as the connection is closed to end it.
+    pub fn set_stream(&mut self, media_type: &str, stream: BodyStream) {
+        self.body_data = None;
+        self.add_header("Content-Type", media_type);
+        self.add_header("Connection", "close");
+        self.takeover = Some(Box::new(Takeover::Stream(stream)));
+    }
+
+    pub(crate) fn take_stream(&mut self) -> Option<BodyStream> {
+        match self.takeover.take().map(|takeover| *takeover) {
+            Some(Takeover::Stream(stream)) => Some(stream),
+            other => {
+                self.takeover = other.map(Box::new);
+                None
+            }
+        }
+    }
+
+    /// Compresses the body, unless there is none or it is already encoded
+    pub fn encode_body(&mut self, encoding: Encoding) {
+        let Some(body_data) = self.body_data.as_mut() else {
+            return;
+        };
+        if body_data.opt_encoding.is_some() || body_data.body.is_empty() {
+            return;
+        }
+        body_data.body = read_and_encode(body_data.body.as_slice(), encoding).expect(READING_MEMORY);
+        body_data.opt_encoding = Some(encoding);
+    }
+
+    /// The length of the body as it would be sent now, before any encoding still to be applied
+    pub fn body_len(&self) -> usize {
+        self.body_data.as_ref().map_or(0, |data| data.body.len())
+    }
+
     pub(crate) fn closing(&self) -> bool {
-        self.dyn_headers.get("Connection").map(Box::as_ref) == Some("close")
+        self.header("Connection") == Some("close")
     }
 }
 
@@ -33,49 +245,42 @@ mod content_type {
     pub enum ContentType {
         Application(Application),
         Text(Text),
+        Other(Box<str>),
     }
 
     impl ContentType {
-        pub(crate) fn as_text(&self) -> &[u8] {
+        pub(crate) fn as_text(&self) -> &str {
             match self {
                 ContentType::Application(a) => match a {
-                    Application::OctetStream => b"application/octet-stream",
+                    Application::OctetStream => "application/octet-stream",
+                    Application::Json => "application/json",
+                    Application::ProblemJson => "application/problem+json",
                 },
                 ContentType::Text(t) => match t {
-                    Text::Plain => b"text/plain",
+                    Text::Plain => "text/plain",
+                    Text::Html => "text/html; charset=utf-8",
                 },
+                ContentType::Other(media_type) => media_type,
             }
         }
     }
 
     pub enum Application {
         OctetStream,
+        Json,
+        ProblemJson,
     }
     pub enum Text {
         Plain,
+        Html,
     }
 }
 
-pub enum ResponseStatus {
-    BadRequest,
-    NotFound,
-    Ok,
-    ServerError,
-    Created,
-}
-
-impl ResponseStatus {
-    pub fn write_to(self, writer: &mut BufWriter<impl Write>) -> io::Result<()> {
-        writer.write_all(self.serialize().as_bytes())
-    }
-
-    fn serialize(self) -> &'static str {
-        match self {
-            Self::Ok => "200 OK",
-            Self::Created => "201 Created",
-            Self::BadRequest => "400 Bad Request",
-            Self::NotFound => "404 Not Found",
-            Self::ServerError => "500 Internal Server Error",
+impl From<ResponseStatus> for Response {
+    fn from(status: ResponseStatus) -> Self {
+        Response {
+            status,
+            ..Default::default()
         }
     }
 }
@@ -85,8 +290,10 @@ impl Default for Response {
         Response {
             version: Version::Ver1_1,
             status: ResponseStatus::Ok,
-            dyn_headers: HashMap::new(),
+            dyn_headers: Vec::new(),
             body_data: None,
+            problem: None,
+            takeover: None,
         }
     }
 }
@@ -94,13 +301,15 @@ impl Default for Response {
 pub const CRLF: [u8; 2] = [b'\r', b'\n'];
 
 impl Response {
-    pub fn write_to(self, stream: impl Write) -> io::Result<()> {
-        let Response {
-            version,
-            status,
-            dyn_headers,
-            body_data,
-        } = self;
+    /// Returns the number of body bytes written
+    pub fn write_to(mut self, stream: impl Write) -> io::Result<u64> {
+        let version = self.version;
+        let body_stream = self.take_stream();
+        let (status, mut headers, body) = self.into_parts();
+        if body_stream.is_some() {
+            // the body ends with the connection instead
+            headers.retain(|(key, _)| !key.eq_ignore_ascii_case("Content-Length"));
+        }
 
         let mut writer = BufWriter::new(stream);
 
@@ -110,95 +319,135 @@ impl Response {
         status.write_to(&mut writer)?;
         writer.write_all(&CRLF)?;
 
-        // Write headers (excluding body related headers)
-        for (key, value) in dyn_headers {
+        for (key, value) in headers {
             writer.write_header(key.as_bytes(), value.as_bytes())?;
         }
+        // Signal end of headers
+        writer.write_all(&CRLF)?;
+
+        writer.write_all(&body)?;
+        let mut body_len = body.len() as u64;
+        if let Some(body_stream) = body_stream {
+            // the client may wait on the head before the first bit of the body is ready
+            writer.flush()?;
+            let mut counter = CountingWriter {
+                inner: &mut writer,
+                count: 0,
+            };
+            body_stream(&mut counter)?;
+            body_len += counter.count;
+        }
+        Ok(body_len)
+    }
 
-        if let Some(BodyData {
+    /// The status, every header including those describing the body, and the body, for
+    /// protocols that frame them differently than [`Response::write_to`]
+    pub(crate) fn into_parts(mut self) -> (ResponseStatus, HeaderList, Vec<u8>) {
+        // the server renders problems itself, knowing its settings, so this is for anyone else
+        self.render_problem(None, &ErrorRendering::default());
+
+        let Response {
+            version: _,
+            status,
+            mut dyn_headers,
+            body_data,
+            problem: _,
+            takeover: _,
+        } = self;
+
+        let body = if let Some(BodyData {
             content_type,
             opt_encoding,
             body,
         }) = body_data
         {
             // add body related headers
-            writer.write_header("Content-Type", content_type.as_text())?;
-            writer.write_header("Content-Length", body.len().to_string().as_bytes())?;
+            dyn_headers.push(("Content-Type".into(), content_type.as_text().into()));
+            dyn_headers.push(("Content-Length".into(), body.len().to_string().into()));
             if let Some(encoding) = opt_encoding {
-                writer.write_header(
-                    b"Content-Encoding",
-                    match encoding {
-                        Encoding::Gzip => b"gzip",
-                    },
-                )?;
+                let encoding = match encoding {
+                    Encoding::Gzip => "gzip",
+                };
+                dyn_headers.push(("Content-Encoding".into(), encoding.into()));
             }
-            // Signal end of headers
-            writer.write_all(&CRLF)?;
-
-            writer.write_all(&body)?;
+            body
         } else {
-            // Signal end of headers
-            writer.write_all(&CRLF)?;
-        }
-        Ok(())
+            // Without this, a client can't tell an empty body from one that ends at close
+            if status.allows_body() {
+                dyn_headers.push(("Content-Length".into(), "0".into()));
+            }
+            Vec::new()
+        };
+        (status, dyn_headers, body)
     }
 }
 
 impl From<error::BadRequest> for Response {
     fn from(error: error::BadRequest) -> Self {
+        let status = match error {
+            error::BadRequest::UnsupportedMediaType(_) => ResponseStatus::UnsupportedMediaType,
+            _ => ResponseStatus::BadRequest,
+        };
+        let problem = Problem {
+            kind: error.kind(),
+            detail: error.to_string(),
+        };
         Response {
-            status: ResponseStatus::BadRequest,
-            dyn_headers: HashMap::from([("Cause".into(), error.to_string().into())]),
+            status,
+            problem: Some(Box::new(problem)),
             ..Self::default()
         }
     }
 }
 
+pub mod informational {
+    use crate::http::response::{Response, ResponseStatus};
+
+    /// Switches the connection to `protocol`
+    pub fn switching_protocols(protocol: &str) -> Response {
+        let mut response = Response::from(ResponseStatus::SwitchingProtocols);
+        response.add_header("Connection", "Upgrade");
+        response.add_header("Upgrade", protocol);
+        response
+    }
+}
+
 pub mod success {
-    use crate::{
-        encoding::{read_and_encode, Encoding},
-        http::{
-            response::{
-                content_type::{Application::OctetStream, ContentType, Text::Plain},
-                BodyData, Response, ResponseStatus,
-            },
-            READING_MEMORY,
+    use crate::http::response::{
+        content_type::{
+            Application::{Json, OctetStream},
+            ContentType,
+            Text::{Html, Plain},
         },
+        BodyData, Response, ResponseStatus,
     };
 
-    pub fn plain_text(str: String, opt_encoding: Option<Encoding>) -> Response {
-        let body = if let Some(encoding) = opt_encoding {
-            read_and_encode(str.as_bytes(), encoding).expect(READING_MEMORY)
-        } else {
-            str.into_bytes()
-        };
+    pub fn plain_text(str: String) -> Response {
+        with_content_type(ContentType::Text(Plain), str.into_bytes())
+    }
 
-        let body_data = BodyData {
-            content_type: ContentType::Text(Plain),
-            opt_encoding,
-            body,
-        };
+    pub fn html(str: String) -> Response {
+        with_content_type(ContentType::Text(Html), str.into_bytes())
+    }
 
-        Response {
-            body_data: Some(body_data),
-            ..Default::default()
-        }
+    pub fn json(value: &serde_json::Value) -> Response {
+        let body = serde_json::to_vec(value).expect("a json Value always serializes");
+        with_content_type(ContentType::Application(Json), body)
     }
-    pub fn octet_stream(unencoded_body: Vec<u8>, opt_encoding: Option<Encoding>) -> Response {
-        // let mut headers = HashM[
-        //     Header::content_type("application/octet-stream"),
-        //     Header::content_length(&body),
-        // ];
 
-        let body = if let Some(encoding) = opt_encoding {
-            read_and_encode(unencoded_body.as_slice(), encoding).expect(READING_MEMORY)
-        } else {
-            unencoded_body
-        };
+    pub fn octet_stream(body: Vec<u8>) -> Response {
+        with_content_type(ContentType::Application(OctetStream), body)
+    }
 
+    /// A 200 response with a body of any media type, e.g. `"image/png"`
+    pub fn with_type(media_type: impl Into<Box<str>>, body: Vec<u8>) -> Response {
+        with_content_type(ContentType::Other(media_type.into()), body)
+    }
+
+    fn with_content_type(content_type: ContentType, body: Vec<u8>) -> Response {
         let body_data = BodyData {
-            content_type: ContentType::Application(OctetStream),
-            opt_encoding,
+            content_type,
+            opt_encoding: None,
             body,
         };
 
@@ -209,10 +458,47 @@ pub mod success {
     }
 
     pub fn created() -> Response {
-        Response {
-            status: ResponseStatus::Created,
-            ..Default::default()
-        }
+        ResponseStatus::Created.into()
+    }
+
+    pub fn no_content() -> Response {
+        ResponseStatus::NoContent.into()
+    }
+}
+
+pub mod redirect {
+    use crate::http::response::{Response, ResponseStatus};
+
+    fn to(status: ResponseStatus, location: &str) -> Response {
+        let mut response = Response::from(status);
+        response.add_header("Location", location);
+        response
+    }
+
+    pub fn moved_permanently(location: &str) -> Response {
+        to(ResponseStatus::MovedPermanently, location)
+    }
+
+    pub fn found(location: &str) -> Response {
+        to(ResponseStatus::Found, location)
+    }
+
+    pub fn see_other(location: &str) -> Response {
+        to(ResponseStatus::SeeOther, location)
+    }
+
+    /// Like [`found`], but the client must not change the request method
+    pub fn temporary(location: &str) -> Response {
+        to(ResponseStatus::TemporaryRedirect, location)
+    }
+
+    /// Like [`moved_permanently`], but the client must not change the request method
+    pub fn permanent(location: &str) -> Response {
+        to(ResponseStatus::PermanentRedirect, location)
+    }
+
+    pub fn not_modified() -> Response {
+        ResponseStatus::NotModified.into()
     }
 }
 
@@ -220,20 +506,222 @@ pub mod server_error {
     use crate::http::response::{Response, ResponseStatus};
 
     pub fn generic() -> Response {
-        Response {
-            status: ResponseStatus::ServerError,
-            ..Default::default()
+        ResponseStatus::InternalServerError.into()
+    }
+
+    pub fn not_implemented() -> Response {
+        ResponseStatus::NotImplemented.into()
+    }
+
+    pub fn bad_gateway() -> Response {
+        ResponseStatus::BadGateway.into()
+    }
+
+    /// `retry_after` is in seconds
+    pub fn service_unavailable(retry_after: Option<u32>) -> Response {
+        let mut response = Response::from(ResponseStatus::ServiceUnavailable);
+        if let Some(seconds) = retry_after {
+            response.add_header("Retry-After", &seconds.to_string());
         }
+        response
+    }
+
+    pub fn gateway_timeout() -> Response {
+        ResponseStatus::GatewayTimeout.into()
+    }
+
+    pub fn http_version_not_supported() -> Response {
+        ResponseStatus::HttpVersionNotSupported.into()
+    }
+
+    pub fn insufficient_storage() -> Response {
+        ResponseStatus::InsufficientStorage.into()
     }
 }
 
 pub mod client_error {
     use crate::http::response::{Response, ResponseStatus};
 
+    pub fn bad_request() -> Response {
+        ResponseStatus::BadRequest.into()
+    }
+
+    /// `challenge` is sent as the `WWW-Authenticate` header, e.g. `Basic realm="files"`
+    pub fn unauthorized(challenge: &str) -> Response {
+        let mut response = Response::from(ResponseStatus::Unauthorized);
+        response.add_header("WWW-Authenticate", challenge);
+        response
+    }
+
+    pub fn forbidden() -> Response {
+        ResponseStatus::Forbidden.into()
+    }
+
     pub fn not_found() -> Response {
-        Response {
-            status: ResponseStatus::NotFound,
-            ..Default::default()
+        ResponseStatus::NotFound.into()
+    }
+
+    /// `allow` lists the supported methods, e.g. `"GET, POST"`
+    pub fn method_not_allowed(allow: &str) -> Response {
+        let mut response = Response::from(ResponseStatus::MethodNotAllowed);
+        response.add_header("Allow", allow);
+        response
+    }
+
+    pub fn not_acceptable() -> Response {
+        ResponseStatus::NotAcceptable.into()
+    }
+
+    pub fn request_timeout() -> Response {
+        let mut response = Response::from(ResponseStatus::RequestTimeout);
+        response.add_header("Connection", "close");
+        response
+    }
+
+    pub fn conflict() -> Response {
+        ResponseStatus::Conflict.into()
+    }
+
+    pub fn gone() -> Response {
+        ResponseStatus::Gone.into()
+    }
+
+    pub fn length_required() -> Response {
+        ResponseStatus::LengthRequired.into()
+    }
+
+    pub fn precondition_failed() -> Response {
+        ResponseStatus::PreconditionFailed.into()
+    }
+
+    pub fn content_too_large() -> Response {
+        ResponseStatus::ContentTooLarge.into()
+    }
+
+    pub fn uri_too_long() -> Response {
+        ResponseStatus::UriTooLong.into()
+    }
+
+    pub fn unsupported_media_type() -> Response {
+        ResponseStatus::UnsupportedMediaType.into()
+    }
+
+    pub fn expectation_failed() -> Response {
+        ResponseStatus::ExpectationFailed.into()
+    }
+
+    pub fn unprocessable_content() -> Response {
+        ResponseStatus::UnprocessableContent.into()
+    }
+
+    /// `retry_after` is in seconds
+    pub fn too_many_requests(retry_after: Option<u32>) -> Response {
+        let mut response = Response::from(ResponseStatus::TooManyRequests);
+        if let Some(seconds) = retry_after {
+            response.add_header("Retry-After", &seconds.to_string());
+        }
+        response
+    }
+
+    pub fn request_header_fields_too_large() -> Response {
+        ResponseStatus::RequestHeaderFieldsTooLarge.into()
+    }
+
+    /// `upgrade` names the protocols the client has to switch to, e.g. `websocket`
+    pub fn upgrade_required(upgrade: &str) -> Response {
+        let mut response = Response::from(ResponseStatus::UpgradeRequired);
+        response.add_header("Connection", "Upgrade");
+        response.add_header("Upgrade", upgrade);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redirect, Response};
+    use crate::http::{error::BadRequest, error_page::ErrorRendering};
+
+    /// The status line and headers as they would be written
+    fn head(response: Response) -> String {
+        let mut written = Vec::new();
+        response.write_to(&mut written).unwrap();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn redirects_to_the_location() {
+        let redirects = [
+            (redirect::moved_permanently("/a"), "301 Moved Permanently"),
+            (redirect::found("/a"), "302 Found"),
+            (redirect::see_other("/a"), "303 See Other"),
+            (redirect::temporary("/a"), "307 Temporary Redirect"),
+            (redirect::permanent("/a"), "308 Permanent Redirect"),
+        ];
+        for (response, status_line) in redirects {
+            assert_eq!(
+                head(response),
+                format!("HTTP/1.1 {status_line}\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n")
+            );
         }
     }
+
+    #[test]
+    fn sends_no_length_when_not_modified() {
+        assert_eq!(head(redirect::not_modified()), "HTTP/1.1 304 Not Modified\r\n\r\n");
+    }
+
+    /// The bad request's problem rendered for `accept`, as content type and body
+    fn problem(accept: Option<&str>, production: bool) -> (String, String) {
+        let mut response = Response::from(BadRequest::MissingHeader("Host"));
+        let rendering = ErrorRendering {
+            production,
+            ..ErrorRendering::default()
+        };
+        response.render_problem(accept, &rendering);
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        let (status, headers, body) = response.into_parts();
+        assert_eq!(status.code(), 400);
+        let content_type = headers
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.to_string())
+            .unwrap();
+        (content_type, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn renders_problems_as_json() {
+        for accept in [None, Some("application/json"), Some("*/*"), Some("image/png")] {
+            let (content_type, body) = problem(accept, false);
+            assert_eq!(content_type, "application/problem+json");
+            let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(
+                json,
+                serde_json::json!({
+                    "type": "urn:http-server:problem:missing-header",
+                    "title": "Bad Request",
+                    "status": 400,
+                    "detail": "Missing header: Host",
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn renders_problems_as_plain_text() {
+        let accept = Some("text/plain, application/problem+json;q=0.5");
+        let (content_type, body) = problem(accept, false);
+        assert_eq!(content_type, "text/plain");
+        assert_eq!(body, "400 Bad Request: Missing header: Host");
+    }
+
+    #[test]
+    fn hides_details_in_production() {
+        let (_, body) = problem(Some("text/plain"), true);
+        assert_eq!(body, "400 Bad Request");
+        let (_, body) = problem(None, true);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json.get("detail"), None);
+        assert_eq!(json["type"], "urn:http-server:problem:missing-header");
+    }
 }
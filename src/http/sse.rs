@@ -0,0 +1,180 @@
+//! Server-sent events: `text/event-stream` responses that push events to the client for as long
+//! as the connection stays open (HTML Living Standard, section 9.2)
+
+use crate::http::{request::Request, response::Response};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+pub const MEDIA_TYPE: &str = "text/event-stream";
+
+/// How long clients of [`counter`] wait before reconnecting
+const RETRY: Duration = Duration::from_secs(3);
+
+/// One event. Fields left unset are not sent.
+#[derive(Debug, Default, Clone)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// What the client sends back in `Last-Event-ID` when it reconnects
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// The type of the event, `message` if unset
+    pub fn event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// How long the client waits before reconnecting once the connection is lost
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        // a line break would end the field early, so ids and types lose theirs
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+        if let Some(event) = &self.event {
+            writeln!(writer, "event: {}", single_line(event))?;
+        }
+        if let Some(id) = &self.id {
+            // a NULL makes the client ignore the id
+            writeln!(writer, "id: {}", single_line(id).replace('\0', ""))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(writer, "retry: {}", retry.as_millis())?;
+        }
+        // every line of the data gets a field of its own, and the client joins them back
+        for line in self.data.split('\n') {
+            writeln!(writer, "data: {}", line.strip_suffix('\r').unwrap_or(line))?;
+        }
+        writeln!(writer)
+    }
+}
+
+/// The body of an event stream, which events are written to as they happen
+pub struct EventStream<'a> {
+    writer: &'a mut dyn Write,
+}
+
+impl EventStream<'_> {
+    /// Sends an event right away. Fails once the client has gone.
+    pub fn send(&mut self, event: &Event) -> io::Result<()> {
+        event.write_to(&mut *self.writer)?;
+        self.writer.flush()
+    }
+
+    /// Sends a comment, which clients ignore, e.g. to keep proxies from timing the connection out
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        for line in text.lines() {
+            writeln!(self.writer, ": {line}")?;
+        }
+        writeln!(self.writer)?;
+        self.writer.flush()
+    }
+}
+
+/// The id of the last event a reconnecting client received
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.header("Last-Event-ID")
+}
+
+/// A response streaming the events `source` sends, until it returns or the client goes away
+pub fn response(source: impl FnOnce(EventStream) -> io::Result<()> + Send + 'static) -> Response {
+    let mut response = Response::default();
+    // events are meant for whoever is listening right now
+    response.add_header("Cache-Control", "no-store");
+    response.set_stream(
+        MEDIA_TYPE,
+        Box::new(|writer| source(EventStream { writer })),
+    );
+    response
+}
+
+/// Where [`counter`] starts for a client that last received the event with `last_event_id`
+fn resume_count(last_event_id: Option<&str>) -> u64 {
+    last_event_id
+        .and_then(|id| id.trim().parse::<u64>().ok())
+        // an ID the count can't go on from is as good as none
+        .and_then(|last| last.checked_add(1))
+        .unwrap_or(0)
+}
+
+/// Counts up once a second, from where a reconnecting client left off
+pub fn counter(request: &Request) -> Response {
+    let mut next = resume_count(last_event_id(request));
+    response(move |mut events| {
+        events.send(
+            &Event::new(next.to_string())
+                .id(next.to_string())
+                .retry(RETRY),
+        )?;
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+            // once the count runs out, the client starts over when it reconnects
+            let Some(following) = next.checked_add(1) else {
+                return Ok(());
+            };
+            next = following;
+            events.send(&Event::new(next.to_string()).id(next.to_string()))?;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resume_count, Event};
+    use std::time::Duration;
+
+    fn written(event: &Event) -> String {
+        let mut out = Vec::new();
+        event.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_every_field_set() {
+        let event = Event::new("1").id("1").event("tick").retry(Duration::from_secs(3));
+        assert_eq!(written(&event), "event: tick\nid: 1\nretry: 3000\ndata: 1\n\n");
+        assert_eq!(written(&Event::new("")), "data: \n\n");
+    }
+
+    #[test]
+    fn keeps_ids_and_types_on_one_line() {
+        let event = Event::new("x").id("a\r\nb\0c").event("ti\nck");
+        assert_eq!(written(&event), "event: tick\nid: abc\ndata: x\n\n");
+    }
+
+    #[test]
+    fn splits_data_into_a_field_per_line() {
+        let event = Event::new("one\r\ntwo\n\nthree");
+        assert_eq!(written(&event), "data: one\ndata: two\ndata: \ndata: three\n\n");
+    }
+
+    #[test]
+    fn resumes_counting_after_the_last_event_id() {
+        assert_eq!(resume_count(None), 0);
+        assert_eq!(resume_count(Some(" 41 ")), 42);
+        assert_eq!(resume_count(Some("not a number")), 0);
+        assert_eq!(resume_count(Some("-1")), 0);
+        assert_eq!(resume_count(Some("18446744073709551614")), u64::MAX);
+        assert_eq!(resume_count(Some("18446744073709551615")), 0);
+        assert_eq!(resume_count(Some("18446744073709551616")), 0);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::http::{
+    error_page::escape_html,
+    request::Request,
+    response::{client_error, redirect, success, Response},
+    Method,
+};
+use jiff::{fmt::strtime, tz::TimeZone, Timestamp};
+use serde::Deserialize;
+use std::{
+    ffi::OsStr,
+    fmt::Write,
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The format of `Last-Modified` and `If-Modified-Since`
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// A filesystem directory served under a URL prefix
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Mount {
+    /// e.g. `/files/`
+    pub prefix: Box<str>,
+    pub root: Box<Path>,
+    /// Whether files may be created with `POST`
+    #[serde(default)]
+    pub writable: bool,
+    /// Whether `GET` on a directory lists its entries
+    #[serde(default)]
+    pub listing: bool,
+    /// Sent as `Cache-Control` with every file
+    #[serde(default)]
+    pub cache_control: Option<Box<str>>,
+}
+
+impl Mount {
+    /// A writable mount without listings, as `--directory` has always provided
+    pub fn writable(prefix: &str, root: Box<Path>) -> Mount {
+        Mount {
+            prefix: prefix.into(),
+            root,
+            writable: true,
+            listing: false,
+            cache_control: None,
+        }
+    }
+
+    /// The part of `target` below this mount, if it is below it at all
+    fn relative<'a>(&self, target: &'a str) -> Option<&'a str> {
+        let prefix = self.prefix.trim_end_matches('/');
+        match target.strip_prefix(prefix)? {
+            "" => Some(""),
+            rest => rest.strip_prefix('/'),
+        }
+    }
+
+    /// Whether a file may be created at `relative` with `POST`: anywhere below the root of a
+    /// writable mount, but not at the root itself
+    fn accepts_post(&self, relative: &str) -> bool {
+        self.writable && !relative.is_empty()
+    }
+
+    /// The methods a target at `relative` may be requested with, as sent in `Allow`
+    fn allow(&self, relative: &str) -> &'static str {
+        match self.accepts_post(relative) {
+            true => "GET, POST, OPTIONS",
+            false => "GET, OPTIONS",
+        }
+    }
+
+    /// Resolves `relative`, still percent-encoded, under the root, refusing anything that would
+    /// escape it
+    fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(relative)?;
+        if decoded.contains(&0) {
+            return None;
+        }
+        let relative = Path::new(OsStr::from_bytes(&decoded));
+        let is_contained = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        is_contained.then(|| self.root.join(relative))
+    }
+}
+
+/// Every mount, matched longest prefix first
+#[derive(Debug, Default, Clone)]
+pub struct Mounts(Vec<Mount>);
+
+impl Mounts {
+    pub fn new(mut mounts: Vec<Mount>) -> Mounts {
+        mounts.sort_by_key(|mount| std::cmp::Reverse(mount.prefix.trim_end_matches('/').len()));
+        Mounts(mounts)
+    }
+
+    /// Where uploads go when no mount is named: the first writable one
+    pub fn upload_root(&self) -> Option<&Path> {
+        self.0.iter().find(|m| m.writable).map(|m| m.root.as_ref())
+    }
+
+    /// Whether `handle` would take the body of `request`, if its target is under a mount
+    pub fn admit(&self, request: &Request) -> Option<Result<(), Response>> {
+        let target = request.target();
+        let (mount, relative) = self.find(&target)?;
+        Some(match request.method() {
+            Method::Post if mount.accepts_post(relative) => Ok(()),
+            Method::Post => Err(client_error::method_not_allowed(mount.allow(relative))),
+            Method::Get | Method::Options => Ok(()),
+        })
+    }
+
+    /// The label of the mount `target` is under in the metrics, e.g. `/files/*`
+    pub fn route(&self, target: &str) -> Option<String> {
+        let (mount, _) = self.find(target)?;
+        Some(format!("{}/*", mount.prefix.trim_end_matches('/')))
+    }
+
+    /// The mount `target` is under, and the part of it below the mount
+    fn find<'a>(&self, target: &'a str) -> Option<(&Mount, &'a str)> {
+        self.0
+            .iter()
+            .find_map(|mount| Some((mount, mount.relative(target)?)))
+    }
+
+    /// Serves `request` if its target is under a mount
+    pub fn handle(&self, request: &Request) -> Option<Result<Response, Response>> {
+        let target = request.target();
+        let (mount, relative) = self.find(&target)?;
+        let Some(path) = mount.resolve(relative) else {
+            log::debug!("refusing to serve {target:?} from outside {:?}", mount.root);
+            return Some(Err(client_error::not_found()));
+        };
+
+        Some(match request.method() {
+            Method::Get => get(mount, &path, &target, request),
+            Method::Post if mount.accepts_post(relative) => {
+                write_creating_parents(&path, request.body())
+                    .map(|()| success::created())
+                    .map_err(Response::from)
+            }
+            Method::Post => Err(client_error::method_not_allowed(mount.allow(relative))),
+            Method::Options => {
+                let mut response = success::no_content();
+                response.add_header("Allow", mount.allow(relative));
+                Ok(response)
+            }
+        })
+    }
+}
+
+fn get(mount: &Mount, path: &Path, target: &str, request: &Request) -> Result<Response, Response> {
+    log::debug!("retreiving {path:?}...");
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        if !mount.listing {
+            return Err(client_error::not_found());
+        }
+        if !target.ends_with('/') {
+            // so that relative links in the listing resolve inside the directory
+            return Ok(redirect::moved_permanently(&format!("{target}/")));
+        }
+        return Ok(success::html(listing(path, target)?));
+    }
+
+    let modified = metadata.modified().ok().and_then(http_date);
+    let not_modified = modified
+        .as_deref()
+        .zip(request.header("If-Modified-Since"))
+        .is_some_and(|(modified, since)| !is_newer(modified, since));
+
+    let mut response = if not_modified {
+        redirect::not_modified()
+    } else {
+        let mut body = Vec::new();
+        File::open(path)?.read_to_end(&mut body)?;
+        success::octet_stream(body)
+    };
+    if let Some(modified) = &modified {
+        response.add_header("Last-Modified", modified);
+    }
+    if let Some(cache_control) = &mount.cache_control {
+        response.add_header("Cache-Control", cache_control);
+    }
+    Ok(response)
+}
+
+fn listing(dir: &Path, target: &str) -> io::Result<String> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let mut name = file_name.to_string_lossy().into_owned();
+            let mut href = percent_encode(file_name.as_bytes());
+            if entry.file_type()?.is_dir() {
+                name.push('/');
+                href.push('/');
+            }
+            Ok((name, href))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let title = escape_html(target);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {title}</title></head>\n\
+        <body>\n<h1>Index of {title}</h1>\n<ul>\n"
+    );
+    for (name, href) in entries {
+        let name = escape_html(&name);
+        let _ = writeln!(html, "<li><a href=\"{href}\">{name}</a></li>");
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Escapes every byte of a file name but the unreserved characters (RFC 3986, section 2.3), so
+/// that it links to the file as a relative reference whatever it contains
+fn percent_encode(name: &[u8]) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for &byte in name {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte));
+            }
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes the `%XX` escapes of a target's path, or `None` if one is malformed
+fn percent_decode(path: &str) -> Option<Vec<u8>> {
+    let hex_digit = |byte: Option<u8>| byte.and_then(|b| char::from(b).to_digit(16));
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+        let (high, low) = (hex_digit(bytes.next())?, hex_digit(bytes.next())?);
+        decoded.push((high << 4 | low) as u8);
+    }
+    Some(decoded)
+}
+
+fn http_date(time: SystemTime) -> Option<String> {
+    let timestamp = Timestamp::try_from(time).ok()?;
+    Some(timestamp.strftime(HTTP_DATE).to_string())
+}
+
+/// Whether `modified` is later than `since`, both HTTP dates. Dates that don't parse are
+/// treated as newer, so that the full file is sent.
+fn is_newer(modified: &str, since: &str) -> bool {
+    let parse = |date: &str| {
+        strtime::parse(HTTP_DATE, date)
+            .and_then(|tm| tm.to_datetime())
+            .and_then(|datetime| datetime.to_zoned(TimeZone::UTC))
+            .map(|zoned| zoned.timestamp())
+            .ok()
+    };
+    match (parse(modified), parse(since)) {
+        (Some(modified), Some(since)) => modified > since,
+        _ => true,
+    }
+}
+
+/// On some OS, creating a file won't work unless it's parents already exist
+fn write_creating_parents<P, C>(path: P, contents: C) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    C: AsRef<[u8]>,
+{
+    if let Some(parent) = path.as_ref().parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_newer, listing, percent_encode, Mount, Mounts};
+    use crate::http::{request::Request, response::Response, Version};
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    /// An empty directory of its own for every test
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("static-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn mount(prefix: &str, root: PathBuf) -> Mount {
+        Mount {
+            listing: true,
+            ..Mount::writable(prefix, root.into())
+        }
+    }
+
+    fn request(target: &str, headers: &[(&str, &str)]) -> Request {
+        let headers: HashMap<Box<str>, Box<str>> =
+            headers.iter().map(|&(key, value)| (key.into(), value.into())).collect();
+        Request::from_parts("GET", target, Version::Ver1_1, headers, Box::new([]), 0).unwrap()
+    }
+
+    /// Whichever response the mounts answered with, success or not
+    fn get(mounts: &Mounts, target: &str, headers: &[(&str, &str)]) -> Response {
+        match mounts.handle(&request(target, headers)) {
+            Some(Ok(response) | Err(response)) => response,
+            None => panic!("no mount for {target}"),
+        }
+    }
+
+    #[test]
+    fn finds_targets_below_the_prefix() {
+        let files = mount("/files/", PathBuf::from("/srv"));
+        assert_eq!(files.relative("/files"), Some(""));
+        assert_eq!(files.relative("/files/"), Some(""));
+        assert_eq!(files.relative("/files/a/b"), Some("a/b"));
+        assert_eq!(files.relative("/filesystem"), None);
+        assert_eq!(files.relative("/"), None);
+
+        let root = mount("/", PathBuf::from("/srv"));
+        assert_eq!(root.relative("/"), Some(""));
+        assert_eq!(root.relative("/files/a"), Some("files/a"));
+    }
+
+    #[test]
+    fn resolves_only_paths_inside_the_root() {
+        let files = mount("/files/", PathBuf::from("/srv"));
+        assert_eq!(files.resolve("a/b.txt"), Some(PathBuf::from("/srv/a/b.txt")));
+        assert_eq!(files.resolve("a%20b%23c"), Some(PathBuf::from("/srv/a b#c")));
+        for escaping in ["../etc/passwd", "a/../../etc", "%2e%2e/etc", "/etc", "a%00", "%zz"] {
+            assert_eq!(files.resolve(escaping), None, "{escaping:?}");
+        }
+    }
+
+    #[test]
+    fn allows_posts_below_the_root_of_writable_mounts() {
+        let writable = mount("/files/", PathBuf::from("/srv"));
+        assert!(writable.accepts_post("a.txt"));
+        assert!(!writable.accepts_post(""));
+        assert_eq!(writable.allow("a.txt"), "GET, POST, OPTIONS");
+        assert_eq!(writable.allow(""), "GET, OPTIONS");
+
+        let read_only = Mount {
+            writable: false,
+            ..writable
+        };
+        assert!(!read_only.accepts_post("a.txt"));
+        assert_eq!(read_only.allow("a.txt"), "GET, OPTIONS");
+    }
+
+    #[test]
+    fn answers_conditional_gets_with_not_modified() {
+        let dir = temp_dir("conditional");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let mounts = Mounts::new(vec![mount("/files/", dir)]);
+
+        let response = get(&mounts, "/files/a.txt", &[]);
+        assert_eq!(response.status().code(), 200);
+        let modified = response.header("Last-Modified").unwrap().to_string();
+
+        let since = [("If-Modified-Since", modified.as_str())];
+        let response = get(&mounts, "/files/a.txt", &since);
+        assert_eq!(response.status().code(), 304);
+
+        let since = [("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")];
+        let response = get(&mounts, "/files/a.txt", &since);
+        assert_eq!(response.status().code(), 200);
+    }
+
+    #[test]
+    fn compares_http_dates() {
+        let earlier = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let later = "Sun, 06 Nov 1994 08:49:38 GMT";
+        assert!(is_newer(later, earlier));
+        assert!(!is_newer(earlier, later));
+        assert!(!is_newer(earlier, earlier));
+        // a date that can't be compared never spares sending the file
+        assert!(is_newer(earlier, "yesterday"));
+    }
+
+    #[test]
+    fn redirects_listings_to_a_trailing_slash() {
+        let dir = temp_dir("listing");
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("100%.txt"), "").unwrap();
+        let mounts = Mounts::new(vec![mount("/files/", dir.clone())]);
+
+        let response = get(&mounts, "/files/sub", &[]);
+        assert_eq!(response.status().code(), 301);
+        assert_eq!(response.header("Location"), Some("/files/sub/"));
+        let response = get(&mounts, "/files/sub/", &[]);
+        assert_eq!(response.status().code(), 200);
+
+        let html = listing(&dir, "/files/").unwrap();
+        assert!(html.contains("<a href=\"100%25.txt\">100%.txt</a>"), "{html}");
+        assert!(html.contains("<a href=\"sub/\">sub/</a>"), "{html}");
+    }
+
+    #[test]
+    fn percent_encodes_all_but_unreserved_characters() {
+        assert_eq!(percent_encode(b"a-Z_0.~"), "a-Z_0.~");
+        assert_eq!(percent_encode("a#b?c d%/<é".as_bytes()), "a%23b%3Fc%20d%25%2F%3C%C3%A9");
+    }
+}
@@ -0,0 +1,188 @@
+use std::io::{self, Write};
+
+/// Boxed so that the rarely used custom reason doesn't bloat every `Response`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomStatus {
+    code: u16,
+    reason: Box<str>,
+}
+
+macro_rules! response_statuses {
+    ($($variant:ident = $code:literal $reason:literal,)*) => {
+        /// Every status code in the IANA HTTP Status Code Registry, plus [`ResponseStatus::Custom`]
+        /// for anything else
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum ResponseStatus {
+            $($variant,)*
+            /// An unregistered code with its own reason phrase. See [`ResponseStatus::custom`]
+            Custom(Box<CustomStatus>),
+        }
+
+        impl ResponseStatus {
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(Self::$variant => $code,)*
+                    Self::Custom(custom) => custom.code,
+                }
+            }
+
+            pub fn reason(&self) -> &str {
+                match self {
+                    $(Self::$variant => $reason,)*
+                    Self::Custom(custom) => &custom.reason,
+                }
+            }
+
+            /// The registered status for `code`, if there is one
+            pub fn from_code(code: u16) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+response_statuses! {
+    Continue = 100 "Continue",
+    SwitchingProtocols = 101 "Switching Protocols",
+    Processing = 102 "Processing",
+    EarlyHints = 103 "Early Hints",
+
+    Ok = 200 "OK",
+    Created = 201 "Created",
+    Accepted = 202 "Accepted",
+    NonAuthoritativeInformation = 203 "Non-Authoritative Information",
+    NoContent = 204 "No Content",
+    ResetContent = 205 "Reset Content",
+    PartialContent = 206 "Partial Content",
+    MultiStatus = 207 "Multi-Status",
+    AlreadyReported = 208 "Already Reported",
+    ImUsed = 226 "IM Used",
+
+    MultipleChoices = 300 "Multiple Choices",
+    MovedPermanently = 301 "Moved Permanently",
+    Found = 302 "Found",
+    SeeOther = 303 "See Other",
+    NotModified = 304 "Not Modified",
+    UseProxy = 305 "Use Proxy",
+    TemporaryRedirect = 307 "Temporary Redirect",
+    PermanentRedirect = 308 "Permanent Redirect",
+
+    BadRequest = 400 "Bad Request",
+    Unauthorized = 401 "Unauthorized",
+    PaymentRequired = 402 "Payment Required",
+    Forbidden = 403 "Forbidden",
+    NotFound = 404 "Not Found",
+    MethodNotAllowed = 405 "Method Not Allowed",
+    NotAcceptable = 406 "Not Acceptable",
+    ProxyAuthenticationRequired = 407 "Proxy Authentication Required",
+    RequestTimeout = 408 "Request Timeout",
+    Conflict = 409 "Conflict",
+    Gone = 410 "Gone",
+    LengthRequired = 411 "Length Required",
+    PreconditionFailed = 412 "Precondition Failed",
+    ContentTooLarge = 413 "Content Too Large",
+    UriTooLong = 414 "URI Too Long",
+    UnsupportedMediaType = 415 "Unsupported Media Type",
+    RangeNotSatisfiable = 416 "Range Not Satisfiable",
+    ExpectationFailed = 417 "Expectation Failed",
+    MisdirectedRequest = 421 "Misdirected Request",
+    UnprocessableContent = 422 "Unprocessable Content",
+    Locked = 423 "Locked",
+    FailedDependency = 424 "Failed Dependency",
+    TooEarly = 425 "Too Early",
+    UpgradeRequired = 426 "Upgrade Required",
+    PreconditionRequired = 428 "Precondition Required",
+    TooManyRequests = 429 "Too Many Requests",
+    RequestHeaderFieldsTooLarge = 431 "Request Header Fields Too Large",
+    UnavailableForLegalReasons = 451 "Unavailable For Legal Reasons",
+
+    InternalServerError = 500 "Internal Server Error",
+    NotImplemented = 501 "Not Implemented",
+    BadGateway = 502 "Bad Gateway",
+    ServiceUnavailable = 503 "Service Unavailable",
+    GatewayTimeout = 504 "Gateway Timeout",
+    HttpVersionNotSupported = 505 "HTTP Version Not Supported",
+    VariantAlsoNegotiates = 506 "Variant Also Negotiates",
+    InsufficientStorage = 507 "Insufficient Storage",
+    LoopDetected = 508 "Loop Detected",
+    NotExtended = 510 "Not Extended",
+    NetworkAuthenticationRequired = 511 "Network Authentication Required",
+}
+
+impl ResponseStatus {
+    /// A status with an arbitrary reason phrase. Registered codes are still allowed, in which case
+    /// the given reason replaces the standard one.
+    ///
+    /// Returns `None` if `code` isn't three digits. CR and LF are stripped from `reason` as they
+    /// would end the status line early.
+    pub fn custom(code: u16, reason: &str) -> Option<Self> {
+        if !(100..=999).contains(&code) {
+            return None;
+        }
+        let reason = reason.replace(['\r', '\n'], "").into();
+        Some(Self::Custom(Box::new(CustomStatus { code, reason })))
+    }
+
+    /// 1xx, 204 and 304 responses never carry content (RFC 9110 section 6.4.1)
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.code(), 100..=199 | 204 | 304)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "{} {}", self.code(), self.reason())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseStatus;
+    use std::collections::HashSet;
+
+    #[test]
+    fn looks_up_registered_codes() {
+        let mut seen = HashSet::new();
+        for code in 0..1000 {
+            let Some(status) = ResponseStatus::from_code(code) else {
+                continue;
+            };
+            assert_eq!(status.code(), code);
+            assert!((100..600).contains(&code), "{code}");
+            assert!(seen.insert(status.reason().to_string()), "{code}");
+        }
+        assert_eq!(seen.len(), 61);
+
+        let status = ResponseStatus::from_code(418);
+        assert!(status.is_none(), "{status:?}");
+        assert_eq!(ResponseStatus::from_code(422), Some(ResponseStatus::UnprocessableContent));
+        assert_eq!(ResponseStatus::UnprocessableContent.reason(), "Unprocessable Content");
+        assert_eq!(ResponseStatus::ContentTooLarge.code(), 413);
+    }
+
+    #[test]
+    fn writes_custom_reasons_on_one_line() {
+        let status = ResponseStatus::custom(299, "Fine\r\nX-Injected: 1").unwrap();
+        let mut line = Vec::new();
+        status.write_to(&mut line).unwrap();
+        assert_eq!(line, b"299 FineX-Injected: 1");
+
+        let status = ResponseStatus::custom(404, "Nothing Here").unwrap();
+        assert_eq!((status.code(), status.reason()), (404, "Nothing Here"));
+
+        assert_eq!(ResponseStatus::custom(99, "Too Short"), None);
+        assert_eq!(ResponseStatus::custom(1000, "Too Long"), None);
+    }
+
+    #[test]
+    fn knows_which_statuses_have_no_content() {
+        for code in [100, 101, 103, 204, 304] {
+            assert!(!ResponseStatus::from_code(code).unwrap().allows_body(), "{code}");
+        }
+        for code in [200, 205, 301, 404, 500] {
+            assert!(ResponseStatus::from_code(code).unwrap().allows_body(), "{code}");
+        }
+        assert!(!ResponseStatus::custom(199, "Informational").unwrap().allows_body());
+    }
+}
@@ -0,0 +1,224 @@
+//! Framing (RFC 6455, section 5)
+
+use super::{CloseCode, Error};
+use std::io::{self, Read, Write};
+
+/// The longest payload of a control frame
+const MAX_CONTROL_LEN: u64 = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_bits(bits: u8) -> Option<OpCode> {
+        match bits {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xA => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    /// Whether this is the last frame of a message
+    pub fin: bool,
+    pub opcode: OpCode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Reads the next frame from a client, unmasking its payload and refusing payloads longer
+    /// than `max_len`
+    pub fn read_from(mut reader: impl Read, max_len: usize) -> Result<Frame, Error> {
+        let mut header = [0; 2];
+        reader.read_exact(&mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        if header[0] & 0x70 != 0 {
+            // no extension is negotiated that would give the RSV bits a meaning
+            return Err(Error::close(CloseCode::ProtocolError, "reserved bits set"));
+        }
+        let opcode = OpCode::from_bits(header[0] & 0x0F)
+            .ok_or(Error::close(CloseCode::ProtocolError, "unknown opcode"))?;
+        if header[1] & 0x80 == 0 {
+            return Err(Error::close(
+                CloseCode::ProtocolError,
+                "client frame is not masked",
+            ));
+        }
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                reader.read_exact(&mut len)?;
+                let len = u64::from_be_bytes(len);
+                if len >> 63 != 0 {
+                    return Err(Error::close(
+                        CloseCode::ProtocolError,
+                        "invalid payload length",
+                    ));
+                }
+                len
+            }
+            len => u64::from(len),
+        };
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_LEN) {
+            return Err(Error::close(
+                CloseCode::ProtocolError,
+                "control frame is fragmented or too long",
+            ));
+        }
+        if len > max_len as u64 {
+            return Err(Error::close(CloseCode::MessageTooBig, "message too long"));
+        }
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    /// Writes a single, final and unmasked frame, as servers send them
+    pub fn write(mut writer: impl Write, opcode: OpCode, payload: &[u8]) -> io::Result<()> {
+        writer.write_all(&[0x80 | opcode.bits()])?;
+        match payload.len() {
+            len @ 0..=125 => writer.write_all(&[len as u8])?,
+            len @ 126..=0xFFFF => {
+                writer.write_all(&[126])?;
+                writer.write_all(&(len as u16).to_be_bytes())?;
+            }
+            len => {
+                writer.write_all(&[127])?;
+                writer.write_all(&(len as u64).to_be_bytes())?;
+            }
+        }
+        writer.write_all(payload)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::{Frame, OpCode};
+    use crate::http::websocket::{CloseCode, Error};
+    use std::io::Cursor;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    /// A frame as a client sends it, masked
+    pub(in crate::http::websocket) fn client_frame(
+        fin: bool,
+        opcode: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut frame = vec![u8::from(fin) << 7 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&MASK);
+        frame.extend(payload.iter().zip(MASK.iter().cycle()).map(|(b, m)| b ^ m));
+        frame
+    }
+
+    fn read(bytes: &[u8]) -> Result<Frame, Error> {
+        Frame::read_from(Cursor::new(bytes), 1 << 20)
+    }
+
+    fn close_code(result: Result<Frame, Error>) -> CloseCode {
+        match result {
+            Err(Error::Close { code, .. }) => code,
+            other => panic!("expected the connection to close, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unmasks_client_frames() {
+        // RFC 6455, section 5.7
+        let frame = read(&[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+        let frame = frame.unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OpCode::Text);
+        assert_eq!(frame.payload, b"Hello");
+    }
+
+    #[test]
+    fn reads_and_writes_extended_payload_lengths() {
+        for len in [125, 126, 0xFFFF, 0x10000] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let frame = read(&client_frame(true, 0x2, &payload)).unwrap();
+            assert_eq!(frame.payload, payload, "{len} bytes");
+
+            let mut written = Vec::new();
+            Frame::write(&mut written, OpCode::Binary, &payload).unwrap();
+            let header_len = match len {
+                0..=125 => 2,
+                126..=0xFFFF => 4,
+                _ => 10,
+            };
+            assert_eq!(written.len(), header_len + len, "{len} bytes");
+            assert_eq!(written[header_len..], payload[..]);
+        }
+        let mut written = Vec::new();
+        Frame::write(&mut written, OpCode::Text, &[0; 300]).unwrap();
+        assert_eq!(written[..4], [0x81, 126, 0x01, 0x2c]);
+    }
+
+    #[test]
+    fn rejects_unmasked_and_malformed_frames() {
+        let unmasked = [0x81, 0x05, b'H', b'e', b'l', b'l', b'o'];
+        assert_eq!(close_code(read(&unmasked)), CloseCode::ProtocolError);
+        let fragmented_ping = client_frame(false, 0x9, b"");
+        assert_eq!(close_code(read(&fragmented_ping)), CloseCode::ProtocolError);
+        let long_ping = client_frame(true, 0x9, &[0; 126]);
+        assert_eq!(close_code(read(&long_ping)), CloseCode::ProtocolError);
+        let reserved_bits = [&[0xc1][..], &client_frame(true, 0x1, b"")[1..]].concat();
+        assert_eq!(close_code(read(&reserved_bits)), CloseCode::ProtocolError);
+        let too_long = Frame::read_from(Cursor::new(client_frame(true, 0x2, &[0; 10])), 9);
+        assert_eq!(close_code(too_long), CloseCode::MessageTooBig);
+    }
+}
@@ -0,0 +1,382 @@
+//! WebSocket (RFC 6455): the opening handshake of `Upgrade: websocket` requests over HTTP/1.1,
+//! and the messages exchanged afterwards on the connection they switched.
+//!
+//! Extensions and subprotocols are never negotiated. A read timeout on the connection is the idle
+//! timeout: the client is pinged once it has been quiet for that long, and the connection closed
+//! if it stays quiet.
+
+mod frame;
+
+use crate::http::{
+    error::BadRequest,
+    request::Request,
+    response::{client_error, informational, Response},
+    Method, Version,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use frame::{Frame, OpCode};
+use sha1::{Digest, Sha1};
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+/// Appended to the client's key to compute `Sec-WebSocket-Accept`
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longer messages are refused with [`CloseCode::MessageTooBig`]
+pub const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Why the server closes a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    Normal = 1000,
+    GoingAway = 1001,
+    ProtocolError = 1002,
+    InvalidData = 1007,
+    MessageTooBig = 1009,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The server closed the connection because of the client
+    #[error("{code:?}: {reason}")]
+    Close {
+        code: CloseCode,
+        reason: &'static str,
+    },
+}
+
+impl Error {
+    fn close(code: CloseCode, reason: &'static str) -> Error {
+        Error::Close { code, reason }
+    }
+}
+
+/// Completes the handshake of a request to open a WebSocket (section 4.2), which `endpoint` then
+/// serves
+pub fn accept(
+    request: &Request,
+    endpoint: fn(WebSocket) -> Result<(), Error>,
+) -> Result<Response, Response> {
+    if *request.method() != Method::Get || *request.version() != Version::Ver1_1 {
+        return Err(BadRequest::WebSocketHandshake("needs a GET request over HTTP/1.1").into());
+    }
+    let has_token = |header, token: &str| {
+        request.header(header).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+        return Err(client_error::upgrade_required("websocket"));
+    }
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        let mut response = client_error::upgrade_required("websocket");
+        response.add_header("Sec-WebSocket-Version", "13");
+        return Err(response);
+    }
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .ok_or(BadRequest::MissingHeader("Sec-WebSocket-Key"))?
+        .trim();
+    if STANDARD.decode(key).map_or(true, |nonce| nonce.len() != 16) {
+        return Err(
+            BadRequest::WebSocketHandshake("Sec-WebSocket-Key is not a 16-byte nonce").into(),
+        );
+    }
+
+    let mut response = informational::switching_protocols("websocket");
+    response.add_header("Sec-WebSocket-Accept", &accept_key(key));
+    response.set_upgrade(Box::new(move |reader, writer| {
+        if let Err(e) = endpoint(WebSocket::new(reader, writer)) {
+            log::info!("WebSocket connection failed: {e}");
+        }
+    }));
+    Ok(response)
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key)
+        .chain_update(ACCEPT_GUID)
+        .finalize();
+    STANDARD.encode(digest)
+}
+
+/// The server's end of an open WebSocket
+pub struct WebSocket<'a> {
+    reader: &'a mut dyn BufRead,
+    writer: &'a mut dyn Write,
+    /// Whether the client was pinged for being quiet and has not sent anything since
+    pinged: bool,
+    /// Whether the server sent a Close frame, after which it sends nothing else
+    closing: bool,
+}
+
+impl<'a> WebSocket<'a> {
+    fn new(reader: &'a mut dyn BufRead, writer: &'a mut dyn Write) -> Self {
+        WebSocket {
+            reader,
+            writer,
+            pinged: false,
+            closing: false,
+        }
+    }
+
+    /// Waits for the next message. Pings are answered along the way. `None` means the
+    /// connection was closed, by either side.
+    ///
+    /// If the client breaks the protocol, the connection is closed with the matching code.
+    pub fn recv(&mut self) -> Result<Option<Message>, Error> {
+        match self.receive() {
+            Err(Error::Close { code, reason }) => {
+                log::debug!("closing WebSocket: {reason}");
+                self.close(code, reason)?;
+                Err(Error::close(code, reason))
+            }
+            result => result,
+        }
+    }
+
+    fn receive(&mut self) -> Result<Option<Message>, Error> {
+        // the opcode and payload of a fragmented message received so far
+        let mut message: Option<(OpCode, Vec<u8>)> = None;
+        loop {
+            self.await_frame()?;
+            let received = message.as_ref().map_or(0, |(_, payload)| payload.len());
+            let frame = Frame::read_from(&mut *self.reader, MAX_MESSAGE_BYTES - received)?;
+            self.pinged = false;
+            match frame.opcode {
+                OpCode::Ping => {
+                    if !self.closing {
+                        Frame::write(&mut *self.writer, OpCode::Pong, &frame.payload)?;
+                    }
+                }
+                OpCode::Pong => {}
+                OpCode::Close => {
+                    let code = close_code(&frame.payload)?;
+                    if !self.closing {
+                        // echoes the code, as the reason is not the server's
+                        let payload = code.map(u16::to_be_bytes).unwrap_or_default();
+                        let payload = if code.is_some() { &payload[..] } else { &[] };
+                        Frame::write(&mut *self.writer, OpCode::Close, payload)?;
+                        self.closing = true;
+                    }
+                    return Ok(None);
+                }
+                OpCode::Continuation => {
+                    let Some((opcode, mut payload)) = message.take() else {
+                        return Err(Error::close(
+                            CloseCode::ProtocolError,
+                            "continuation without a message",
+                        ));
+                    };
+                    payload.extend(frame.payload);
+                    if frame.fin {
+                        return message_from(opcode, payload).map(Some);
+                    }
+                    message = Some((opcode, payload));
+                }
+                OpCode::Text | OpCode::Binary if message.is_some() => {
+                    return Err(Error::close(
+                        CloseCode::ProtocolError,
+                        "new message before the last one ended",
+                    ));
+                }
+                opcode if frame.fin => return message_from(opcode, frame.payload).map(Some),
+                opcode => message = Some((opcode, frame.payload)),
+            }
+        }
+    }
+
+    /// Waits until a frame starts arriving, pinging the client the first time the read times out
+    fn await_frame(&mut self) -> Result<(), Error> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok([]) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if self.pinged || self.closing {
+                        return Err(Error::close(CloseCode::GoingAway, "client is idle"));
+                    }
+                    Frame::write(&mut *self.writer, OpCode::Ping, &[])?;
+                    self.pinged = true;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), Error> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OpCode::Text, text.as_bytes()),
+            Message::Binary(data) => (OpCode::Binary, data.as_slice()),
+        };
+        Ok(Frame::write(&mut *self.writer, opcode, payload)?)
+    }
+
+    /// Starts closing the connection. [`WebSocket::recv`] returns `None` once the client agrees.
+    pub fn close(&mut self, code: CloseCode, reason: &str) -> Result<(), Error> {
+        if self.closing {
+            return Ok(());
+        }
+        self.closing = true;
+        let mut payload = (code as u16).to_be_bytes().to_vec();
+        // what fits in a control frame, cut at a character boundary
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        Ok(Frame::write(&mut *self.writer, OpCode::Close, &payload)?)
+    }
+}
+
+fn message_from(opcode: OpCode, payload: Vec<u8>) -> Result<Message, Error> {
+    match opcode {
+        OpCode::Text => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| Error::close(CloseCode::InvalidData, "text message is not UTF-8")),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+/// The status code of a Close frame's payload, which a client may leave out (section 5.5.1)
+fn close_code(payload: &[u8]) -> Result<Option<u16>, Error> {
+    let Some((code, reason)) = payload.split_first_chunk::<2>() else {
+        return match payload {
+            [] => Ok(None),
+            _ => Err(Error::close(
+                CloseCode::ProtocolError,
+                "truncated close code",
+            )),
+        };
+    };
+    let code = u16::from_be_bytes(*code);
+    // the codes defined so far, and those left to libraries and applications (section 7.4)
+    if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+        return Err(Error::close(CloseCode::ProtocolError, "invalid close code"));
+    }
+    if std::str::from_utf8(reason).is_err() {
+        return Err(Error::close(
+            CloseCode::InvalidData,
+            "close reason is not UTF-8",
+        ));
+    }
+    Ok(Some(code))
+}
+
+/// Sends every message back as it was received
+pub fn echo(mut socket: WebSocket) -> Result<(), Error> {
+    while let Some(message) = socket.recv()? {
+        socket.send(&message)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame::tests::client_frame, CloseCode, Error, Message, WebSocket};
+    use std::io::Cursor;
+
+    /// Receives every message the client sends in `frames`, then returns them along with what
+    /// the server wrote back and how the connection ended
+    fn receive(frames: &[Vec<u8>]) -> (Vec<Message>, Vec<u8>, Result<(), Error>) {
+        let mut reader = Cursor::new(frames.concat());
+        let mut writer = Vec::new();
+        let mut socket = WebSocket::new(&mut reader, &mut writer);
+        let mut messages = Vec::new();
+        let end = loop {
+            match socket.recv() {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        (messages, writer, end)
+    }
+
+    #[test]
+    fn reassembles_fragments_around_control_frames() {
+        let (messages, written, end) = receive(&[
+            client_frame(false, 0x1, b"Hel"),
+            client_frame(true, 0x9, b"ping"),
+            client_frame(false, 0x0, b"lo, "),
+            client_frame(true, 0xA, b""),
+            client_frame(true, 0x0, b"world"),
+            client_frame(true, 0x2, &[1, 2]),
+            client_frame(true, 0x8, &1000u16.to_be_bytes()),
+        ]);
+        assert!(end.is_ok());
+        assert_eq!(
+            messages,
+            [Message::Text("Hello, world".into()), Message::Binary(vec![1, 2])]
+        );
+        // the pong, then the close echoed back
+        assert_eq!(written, b"\x8a\x04ping\x88\x02\x03\xe8");
+    }
+
+    #[test]
+    fn rejects_continuations_out_of_place() {
+        let (_, _, end) = receive(&[client_frame(true, 0x0, b"stray")]);
+        assert!(matches!(end, Err(Error::Close { code: CloseCode::ProtocolError, .. })));
+        let interrupted = [client_frame(false, 0x1, b"a"), client_frame(true, 0x1, b"b")];
+        let (_, _, end) = receive(&interrupted);
+        assert!(matches!(end, Err(Error::Close { code: CloseCode::ProtocolError, .. })));
+    }
+
+    #[test]
+    fn closes_unmasked_connections_with_a_protocol_error() {
+        let (messages, written, end) = receive(&[vec![0x81, 0x01, b'a']]);
+        assert!(messages.is_empty());
+        assert!(matches!(end, Err(Error::Close { code: CloseCode::ProtocolError, .. })));
+        assert_eq!(written[..4], [0x88, 0x1c, 0x03, 0xea]);
+    }
+
+    #[test]
+    fn handles_close_codes() {
+        let close = |payload: &[u8]| receive(&[client_frame(true, 0x8, payload)]);
+
+        let (_, written, end) = close(&[]);
+        assert!(end.is_ok());
+        assert_eq!(written, [0x88, 0x00]);
+
+        let (_, written, end) = close(b"\x0f\xa0bye");
+        assert!(end.is_ok());
+        assert_eq!(written, [0x88, 0x02, 0x0f, 0xa0]);
+
+        for (payload, expected) in [
+            (&b"\x03"[..], CloseCode::ProtocolError),
+            (b"\x03\xe7", CloseCode::ProtocolError),
+            (b"\x03\xec", CloseCode::ProtocolError),
+            (b"\x03\xe8\xff", CloseCode::InvalidData),
+        ] {
+            let (_, written, end) = close(payload);
+            let Err(Error::Close { code, .. }) = end else {
+                panic!("accepted the close payload {payload:?}");
+            };
+            assert_eq!(code, expected, "{payload:?}");
+            assert_eq!(written[2..4], (expected as u16).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn rejects_text_that_is_not_utf8() {
+        let (_, _, end) = receive(&[client_frame(true, 0x1, &[0xff])]);
+        assert!(matches!(end, Err(Error::Close { code: CloseCode::InvalidData, .. })));
+    }
+}
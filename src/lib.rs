@@ -0,0 +1,17 @@
+//! An HTTP/1.x and HTTP/2 server, over TCP, TLS or Unix sockets. An event loop waits on the
+//! connections and hands each HTTP/1 request that has arrived to a thread pool, while HTTP/2 and
+//! upgraded connections get a thread of their own. Requests are answered through a middleware
+//! chain and a router. Start one with `Server::builder()`.
+
+pub mod access_log;
+pub mod encoding;
+pub mod health;
+pub mod http;
+pub mod listener;
+pub mod metrics;
+pub mod server;
+pub mod thread_pool;
+pub mod tls;
+pub mod trace;
+
+pub use server::{Server, ServerBuilder, StopHandle};
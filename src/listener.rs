@@ -0,0 +1,294 @@
+use crate::http::connection::Peer;
+use std::{
+    fs::{self, Permissions},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The first file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Where the server accepts connections from
+pub(crate) enum Socket {
+    Tcp(TcpListener),
+    Unix {
+        listener: UnixListener,
+        /// Set if the server created the socket file, which is then removed again when dropped
+        owned_path: Option<PathBuf>,
+    },
+}
+
+/// A connection accepted from a `Socket`
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// What to connect to for waking up a thread blocked accepting on a `Socket`
+#[derive(Debug, Clone)]
+pub(crate) enum WakeAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Socket {
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Socket::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Socket::Unix { listener, .. } => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Socket::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Socket::Unix { listener, .. } => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Socket::Tcp(listener) => listener.local_addr().ok(),
+            Socket::Unix { .. } => None,
+        }
+    }
+
+    pub fn wake_addr(&self) -> Option<WakeAddr> {
+        match self {
+            Socket::Tcp(listener) => listener.local_addr().ok().map(WakeAddr::Tcp),
+            Socket::Unix { listener, .. } => listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+                .map(WakeAddr::Unix),
+        }
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Socket::Tcp(listener) => listener.as_raw_fd(),
+            Socket::Unix { listener, .. } => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Socket::Unix {
+            owned_path: Some(path),
+            ..
+        } = self
+        {
+            if let Err(e) = fs::remove_file(&*path) {
+                log::warn!("failed to remove socket {path:?}: {e}");
+            }
+        }
+    }
+}
+
+impl Stream {
+    pub fn peer(&self) -> Option<Peer> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok().map(Peer::Tcp),
+            Stream::Unix(stream) => Peer::of_unix(stream)
+                .inspect_err(|e| log::warn!("failed to read peer credentials: {e}"))
+                .ok(),
+        }
+    }
+
+    pub fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl WakeAddr {
+    /// Connects and disconnects straight away
+    pub fn wake(&self) {
+        let _ = match self {
+            WakeAddr::Tcp(addr) => TcpStream::connect(connectable(*addr)).map(drop),
+            WakeAddr::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+    }
+}
+
+/// Listeners bound to the unspecified address are reached over loopback
+fn connectable(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    addr
+}
+
+/// Binds a Unix domain socket at `path`, replacing a stale socket file left behind by a server
+/// that didn't shut down cleanly. `mode` sets the file's permissions, e.g. `0o660`.
+pub(crate) fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<Socket> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{path:?} is in use by another server"),
+                ));
+            }
+            log::info!("removing stale socket {path:?}");
+            fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path:?} exists and is not a socket"),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let socket = Socket::Unix {
+        listener,
+        owned_path: Some(path.into()),
+    };
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    Ok(socket)
+}
+
+/// Takes over a listening socket inherited from the parent process, e.g. from systemd or a
+/// previous instance of the server handing over during an upgrade
+pub(crate) fn from_fd(fd: RawFd) -> io::Result<Socket> {
+    let invalid = |reason: &str| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("fd {fd} {reason}"))
+    };
+    if getsockopt(fd, libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(invalid("is not a stream socket"));
+    }
+    if getsockopt(fd, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid("is not listening"));
+    }
+    // SAFETY: `fd` is an open descriptor (getsockopt succeeded), owned by nothing else in this
+    // process since it was inherited
+    unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // SAFETY: `addr` and `len` describe a writable `sockaddr_storage`
+    let result =
+        unsafe { libc::getsockname(fd, (&mut addr as *mut libc::sockaddr_storage).cast(), &mut len) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: as above, and the socket family matches the listener type
+    match i32::from(addr.ss_family) {
+        libc::AF_INET | libc::AF_INET6 => Ok(Socket::Tcp(unsafe { TcpListener::from_raw_fd(fd) })),
+        libc::AF_UNIX => Ok(Socket::Unix {
+            listener: unsafe { UnixListener::from_raw_fd(fd) },
+            owned_path: None,
+        }),
+        _ => Err(invalid("is of an unsupported address family")),
+    }
+}
+
+/// The descriptors passed through systemd socket activation, if the server was started that way.
+/// The environment variables are cleared, so that child processes don't pick them up too.
+pub fn systemd_fds() -> Vec<RawFd> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .filter(|_| for_us)
+        .unwrap_or(0);
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).collect()
+}
+
+fn getsockopt(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` describe a writable `c_int`, as both options expect
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
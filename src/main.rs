@@ -1,85 +1,204 @@
-mod encoding;
-mod http;
-mod thread_pool;
+mod config;
 
-use crate::{http::request::RequestSource, http::HTTPCarrier, thread_pool::ThreadPool};
+use crate::config::{Args, Config};
 use clap::Parser;
+use codecrafters_http_server::{
+    access_log::AccessLog,
+    health::HealthEndpoints,
+    http::middleware::{Chain, Compression, Cors, Logging},
+    http::error_page::ErrorRendering,
+    http::request::AppState,
+    http::static_files::Mounts,
+    metrics::{Metrics, MetricsEndpoint},
+    thread_pool::ThreadPool,
+    tls::TlsAcceptor,
+    trace, Server, StopHandle,
+};
 use env_logger::{Target, WriteStyle::Always};
-use log::{Level::Debug, LevelFilter};
+use log::LevelFilter;
 use std::{
-    net::{TcpListener, TcpStream},
-    path::Path,
-    sync::OnceLock,
+    io::Write,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 
-#[derive(Parser)]
-pub struct Args {
-    #[arg(long)]
-    directory: Option<Box<Path>>,
-}
-
-pub static DIRECTORY: OnceLock<Box<Path>> = OnceLock::new();
-
 fn main() {
-    env_logger::Builder::new()
-        .filter_level(LevelFilter::Debug)
-        .target(Target::Stdout)
-        .format_timestamp(None)
-        .write_style(Always)
-        .parse_default_env()
-        .init();
-    dbg!(log::max_level());
-
     let args = Args::parse();
+    let check_only = args.check_config;
+    let config = match Config::load(args) {
+        Ok(_) if check_only => {
+            println!("configuration OK");
+            return;
+        }
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    };
+
+    init_logger(config.log_level);
+    log::debug!("log level: {}", log::max_level());
 
-    if let Some(dir) = args.directory {
-        DIRECTORY.get_or_init(|| dir);
-    } else {
-        log::warn!("DIRECTORY not set!");
+    if config.mounts.is_empty() {
+        log::warn!("no static mounts configured!");
     }
+    let writable_roots = config
+        .mounts
+        .iter()
+        .filter(|mount| mount.writable)
+        .map(|mount| mount.root.clone())
+        .collect();
+    let errors = ErrorRendering {
+        pages: config.error_pages,
+        production: config.production,
+    };
+    let state = Arc::new(AppState {
+        mounts: Mounts::new(config.mounts),
+        errors: errors.clone(),
+    });
 
-    let pool = ThreadPool::auto(5);
+    let pool = match config.workers {
+        Some(size) => ThreadPool::new(size),
+        None => ThreadPool::auto(5),
+    };
+    let pool_stats = pool.stats();
 
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    let metrics = (config.metrics_path.is_some() || config.metrics_listen.is_some())
+        .then(|| Arc::new(Metrics::new(Arc::clone(&pool_stats))));
+    let metrics_path = config.metrics_path.unwrap_or_else(|| "/metrics".into());
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                pool.execute(|| handle_connection(stream));
+    let draining = Arc::new(AtomicBool::new(false));
+
+    let mut builder = Server::builder()
+        .pool(pool)
+        .errors(errors)
+        .limits(config.limits)
+        .read_timeout(config.read_timeout)
+        .request_timeout(Some(config.request_timeout))
+        .write_timeout(config.write_timeout)
+        .admission({
+            let state = Arc::clone(&state);
+            move |request| request.admit(&state)
+        })
+        .route_label({
+            let state = Arc::clone(&state);
+            // the endpoints answered by middleware are routes of their own, too
+            let mut own_routes = vec![config.liveness_path.clone(), config.readiness_path.clone()];
+            if metrics.is_some() && config.metrics_listen.is_none() {
+                own_routes.push(metrics_path.clone());
             }
-            Err(e) => {
-                log::error!("connection failed: {e}");
+            move |request| {
+                let target = request.target();
+                match own_routes.iter().find(|path| path.as_ref() == target) {
+                    Some(path) => path.to_string().into(),
+                    None => request.route(&state),
+                }
             }
+        })
+        .router(move |request| request.handle(&state))
+        .middleware(Logging)
+        .middleware(HealthEndpoints {
+            liveness_path: config.liveness_path,
+            readiness_path: config.readiness_path,
+            checks: config.readiness_checks,
+            max_queue_depth: config.max_queue_depth.unwrap_or(pool_stats.size()),
+            draining: Arc::clone(&draining),
+            pool: pool_stats,
+            writable_roots,
+        });
+    for addr in config.listen {
+        builder = builder.bind(addr);
+    }
+    for fd in config.fds {
+        builder = builder.bind_fd(fd);
+    }
+    if let Some(path) = config.unix_socket {
+        builder = builder.bind_unix(path, config.unix_socket_mode);
+    }
+    if !config.tls_listen.is_empty() {
+        // one acceptor, so that the certificates are loaded and reloaded once for all listeners
+        let acceptor = TlsAcceptor::new(config.tls_certificates)
+            .unwrap_or_else(|e| panic!("failed to set up TLS: {e}"));
+        let acceptor = Arc::new(acceptor);
+        for addr in config.tls_listen {
+            builder = builder.bind_tls(addr, Arc::clone(&acceptor));
         }
     }
-}
+    if let Some(metrics) = &metrics {
+        builder = builder.metrics(Arc::clone(metrics));
+        let endpoint = MetricsEndpoint {
+            path: metrics_path,
+            metrics: Arc::clone(metrics),
+        };
+        builder = match config.metrics_listen {
+            Some(addr) => builder.admin_listener(addr, Chain::default().with(endpoint)),
+            None => builder.middleware(endpoint),
+        };
+    }
+    if config.cors_origins.iter().any(|origin| origin.as_ref() == "*") {
+        builder = builder.middleware(Cors::any_origin());
+    } else if !config.cors_origins.is_empty() {
+        builder = builder.middleware(Cors::origins(config.cors_origins));
+    }
+    if let Some(min_bytes) = config.compression_min_bytes {
+        builder = builder.middleware(Compression { min_bytes });
+    }
+    if let Some(path) = config.access_log {
+        let access_log = AccessLog::open(&path, config.access_log_format)
+            .unwrap_or_else(|e| panic!("failed to open access log {path:?}: {e}"));
+        builder = builder.access_log(access_log);
+    }
 
-fn handle_connection(mut stream: TcpStream) {
-    log::info!("accepted new connection");
+    let server = builder
+        .build()
+        .unwrap_or_else(|e| panic!("failed to start the server: {e}"));
+    let grace = Duration::from_secs(config.drain_secs);
+    spawn_drain_watcher(draining, grace, server.stop_handle());
+    server.run();
+}
 
-    loop {
-        let response = match stream.read_request() {
-            Ok(request) => request.handle(),
-            Err(Some(err_response)) => err_response,
-            Err(None) => break, // Stream has been closed
-        };
+fn init_logger(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(level)
+        .target(Target::Stdout)
+        .format(|buf, record| {
+            let level_style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{level_style}{:<5}{level_style:#} {}]{} {}",
+                record.level(),
+                record.target(),
+                trace::log_context(),
+                record.args()
+            )
+        })
+        .write_style(Always)
+        .parse_default_env()
+        .init();
+}
 
-        let close_sent = response.closing();
+/// Marks the server as draining on SIGTERM or SIGINT, then stops it once `grace` has passed
+fn spawn_drain_watcher(draining: Arc<AtomicBool>, grace: Duration, server: StopHandle) {
+    use signal_hook::{consts::TERM_SIGNALS, flag};
+    for &signal in TERM_SIGNALS {
+        // the second signal finds the flag already set and exits straight away
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(&draining))
+            .and_then(|_| flag::register(signal, Arc::clone(&draining)))
+            .unwrap_or_else(|e| panic!("failed to register a handler for signal {signal}: {e}"));
+    }
 
-        match stream.respond(response) {
-            Err(io_error) => {
-                if log::log_enabled!(Debug) {
-                    log::debug!("failed to write response to stream: {io_error:?}\n{stream:?}");
-                } else {
-                    log::info!("failed to write response to stream: {io_error}");
-                }
-            }
-            Ok(()) => {
-                log::trace!("response sent");
-            }
+    thread::spawn(move || {
+        while !draining.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
         }
-        if close_sent {
-            break
-        }
-    }
+        log::info!("draining: stopping in {grace:?}");
+        thread::sleep(grace);
+        server.stop();
+    });
 }
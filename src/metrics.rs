@@ -0,0 +1,311 @@
+use crate::{
+    http::{
+        middleware::{Middleware, Next},
+        request::Request,
+        response::{success, Response},
+        Method,
+    },
+    thread_pool::PoolStats,
+};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+/// Upper bounds, in seconds, of the request latency histogram buckets
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The label of the mount or endpoint a request is routed to, e.g. `/files/*`
+type Route = Cow<'static, str>;
+
+/// Server-wide counters, rendered in the Prometheus text exposition format
+pub struct Metrics {
+    /// Keyed by method, route and status
+    requests: Mutex<BTreeMap<(&'static str, Route, u16), u64>>,
+    /// Keyed by method and route
+    latency: Mutex<BTreeMap<(&'static str, Route), Histogram>>,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    active_connections: AtomicUsize,
+    pool: Arc<PoolStats>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Not cumulative; each count is only for its own bucket. The last is `+Inf`
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+/// What identifies a request in the metrics
+pub struct Labels {
+    pub method: &'static str,
+    pub route: Route,
+}
+
+impl Labels {
+    /// For requests that couldn't be parsed
+    pub const UNPARSED: Labels = Labels {
+        method: "unknown",
+        route: Cow::Borrowed("unmatched"),
+    };
+}
+
+impl Metrics {
+    pub fn new(pool: Arc<PoolStats>) -> Self {
+        Metrics {
+            requests: Mutex::default(),
+            latency: Mutex::default(),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            active_connections: AtomicUsize::new(0),
+            pool,
+        }
+    }
+
+    pub fn record(&self, labels: &Labels, status: u16, latency: Duration, received: u64, sent: u64) {
+        let Labels { method, route } = labels;
+        *lock(&self.requests)
+            .entry((method, route.clone(), status))
+            .or_default() += 1;
+
+        let seconds = latency.as_secs_f64();
+        let mut latencies = lock(&self.latency);
+        let histogram = latencies.entry((method, route.clone())).or_default();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.sum += seconds;
+        histogram.count += 1;
+
+        self.bytes_received.fetch_add(received, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(sent, Ordering::Relaxed);
+    }
+
+    /// Counts the connection as active until the returned guard is dropped
+    pub fn connection_opened(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(Arc::clone(self))
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "http_requests_total", "counter", "Requests answered");
+        for ((method, route, status), count) in lock(&self.requests).iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{method}\",route=\"{route}\",status=\"{status}\"}} {count}"
+            );
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time from a request being read to its response being sent",
+        );
+        for ((method, route), histogram) in lock(&self.latency).iter() {
+            let labels = format!("method=\"{method}\",route=\"{route}\"");
+            let mut cumulative = 0;
+            let bounds = LATENCY_BUCKETS.iter().map(f64::to_string);
+            for (le, count) in bounds.chain(["+Inf".into()]).zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+
+        let gauges = [
+            (
+                "http_received_bytes_total",
+                "counter",
+                "Bytes of requests read",
+                self.bytes_received.load(Ordering::Relaxed),
+            ),
+            (
+                "http_sent_bytes_total",
+                "counter",
+                "Bytes of responses written",
+                self.bytes_sent.load(Ordering::Relaxed),
+            ),
+            (
+                "http_active_connections",
+                "gauge",
+                "Open client connections",
+                self.active_connections.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "thread_pool_workers",
+                "gauge",
+                "Worker threads in the pool",
+                self.pool.size() as u64,
+            ),
+            (
+                "thread_pool_busy_workers",
+                "gauge",
+                "Workers currently executing a job",
+                self.pool.busy() as u64,
+            ),
+            (
+                "thread_pool_queue_depth",
+                "gauge",
+                "Jobs waiting for a free worker",
+                self.pool.queued() as u64,
+            ),
+        ];
+        for (name, kind, help, value) in gauges {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        out
+    }
+}
+
+pub struct ConnectionGuard(Arc<Metrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Answers `GET` requests for `path` with the current metrics
+pub struct MetricsEndpoint {
+    pub path: Box<str>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl Middleware for MetricsEndpoint {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        if *request.method() != Method::Get || request.target() != *self.path {
+            return next.run(request);
+        }
+        success::with_type(
+            "text/plain; version=0.0.4",
+            self.metrics.render().into_bytes(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Labels, Metrics};
+    use crate::thread_pool::PoolStats;
+    use std::{sync::Arc, time::Duration};
+
+    fn labels(route: &'static str) -> Labels {
+        Labels {
+            method: "GET",
+            route: route.into(),
+        }
+    }
+
+    #[test]
+    fn renders_cumulative_buckets() {
+        let metrics = Metrics::new(Arc::new(PoolStats::default()));
+        for millis in [3, 20, 20, 700, 60_000] {
+            metrics.record(&labels("/files/*"), 200, Duration::from_millis(millis), 10, 100);
+        }
+        let rendered = metrics.render();
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        let buckets: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("http_request_duration_seconds_bucket"))
+            .collect();
+        let labels = "{method=\"GET\",route=\"/files/*\",le=";
+        let expected = [
+            "\"0.005\"} 1",
+            "\"0.01\"} 1",
+            "\"0.025\"} 3",
+            "\"0.05\"} 3",
+            "\"0.1\"} 3",
+            "\"0.25\"} 3",
+            "\"0.5\"} 3",
+            "\"1\"} 4",
+            "\"2.5\"} 4",
+            "\"5\"} 4",
+            "\"10\"} 4",
+            "\"+Inf\"} 5",
+        ];
+        let expected: Vec<String> = expected.iter().map(|b| format!("{labels}{b}")).collect();
+        assert_eq!(buckets, expected);
+
+        let count = r#"http_request_duration_seconds_count{method="GET",route="/files/*"} 5"#;
+        assert!(lines.contains(&count), "{rendered}");
+        let sum = r#"http_request_duration_seconds_sum{method="GET",route="/files/*"} 60.743"#;
+        assert!(lines.contains(&sum), "{rendered}");
+    }
+
+    #[test]
+    fn renders_the_exposition_format() {
+        let metrics = Arc::new(Metrics::new(Arc::new(PoolStats::default())));
+        metrics.record(&labels("/healthz"), 200, Duration::ZERO, 10, 100);
+        metrics.record(&labels("/healthz"), 200, Duration::ZERO, 10, 100);
+        metrics.record(&Labels::UNPARSED, 400, Duration::ZERO, 5, 50);
+        let _connection = metrics.connection_opened();
+        let rendered = metrics.render();
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        for line in [
+            "# HELP http_requests_total Requests answered",
+            "# TYPE http_requests_total counter",
+            r#"http_requests_total{method="GET",route="/healthz",status="200"} 2"#,
+            r#"http_requests_total{method="unknown",route="unmatched",status="400"} 1"#,
+            "# TYPE http_request_duration_seconds histogram",
+            "http_received_bytes_total 25",
+            "http_sent_bytes_total 250",
+            "# TYPE http_active_connections gauge",
+            "http_active_connections 1",
+            "thread_pool_queue_depth 0",
+        ] {
+            assert!(lines.contains(&line), "missing {line:?} in {rendered}");
+        }
+        // every metric is described before its samples
+        let mut described = Vec::new();
+        for line in &lines {
+            match line.strip_prefix("# TYPE ") {
+                Some(typed) => described.push(typed.split(' ').next().unwrap()),
+                None if line.starts_with('#') => {}
+                None => assert!(
+                    described.iter().any(|name| line.starts_with(name)),
+                    "undescribed {line:?}"
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,893 @@
+mod reactor;
+
+use crate::{
+    access_log::{AccessLog, Record, RequestInfo},
+    http::{
+        connection::{Connection, Peer},
+        error_page::ErrorRendering,
+        h2,
+        middleware::{Chain, Middleware},
+        request::{AppState, Head, Limits, Request, RequestSource},
+        response::{client_error, informational, server_error, Response},
+        BytesSent, HTTPCarrier, Version,
+    },
+    listener::{self, Socket, Stream, WakeAddr},
+    metrics::{Labels, Metrics},
+    server::reactor::{Client, Reactor},
+    thread_pool::ThreadPool,
+    tls::TlsAcceptor,
+    trace,
+};
+use log::Level::Debug;
+use std::{
+    any::Any,
+    borrow::Cow,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    panic::{self, AssertUnwindSafe},
+    os::fd::RawFd,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Answers every request that the middleware chain passes on
+pub type Router = Box<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// Decides from its head alone whether a request that sent `Expect: 100-continue` may go on to
+/// send its body, or is answered with the response right away
+pub type Admission = Box<dyn Fn(&Request) -> Result<(), Response> + Send + Sync>;
+
+/// Labels a request in the metrics by the route it takes, from a bounded set of labels
+pub type RouteLabel = Box<dyn Fn(&Request) -> Cow<'static, str> + Send + Sync>;
+
+/// A bound server, ready to `run`
+pub struct Server {
+    listeners: Vec<Listener>,
+    admin: Option<(Socket, Services)>,
+    pool: ThreadPool,
+    services: Arc<Services>,
+    stopping: Arc<AtomicBool>,
+}
+
+struct Listener {
+    socket: Socket,
+    /// Set for HTTPS listeners
+    tls: Option<Arc<TlsAcceptor>>,
+}
+
+/// A listener to set up on `build`
+enum Bind {
+    Tcp(SocketAddr, Option<Arc<TlsAcceptor>>),
+    Unix { path: PathBuf, mode: Option<u32> },
+    Fd(RawFd),
+}
+
+/// Everything needed to serve a connection, shared by all workers
+struct Services {
+    chain: Chain,
+    router: Router,
+    admission: Admission,
+    route_label: RouteLabel,
+    access_log: Option<AccessLog>,
+    metrics: Option<Arc<Metrics>>,
+    /// Gives bodies to the error responses that the router doesn't, e.g. for unparsable requests
+    errors: ErrorRendering,
+    limits: Limits,
+    read_timeout: Option<Duration>,
+    /// How long a request may take to arrive in full, however steadily it trickles in
+    request_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+pub struct ServerBuilder {
+    binds: Vec<Bind>,
+    chain: Chain,
+    router: Option<Router>,
+    admission: Option<Admission>,
+    route_label: Option<RouteLabel>,
+    pool: Option<ThreadPool>,
+    workers: Option<u8>,
+    errors: ErrorRendering,
+    limits: Limits,
+    read_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    access_log: Option<AccessLog>,
+    metrics: Option<Arc<Metrics>>,
+    admin: Option<(SocketAddr, Chain)>,
+}
+
+/// Stops a running `Server` from accepting connections. Connections already accepted are served
+/// until they close.
+#[derive(Clone)]
+pub struct StopHandle {
+    stopping: Arc<AtomicBool>,
+    addrs: Vec<WakeAddr>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            binds: Vec::new(),
+            chain: Chain::default(),
+            router: None,
+            admission: None,
+            route_label: None,
+            pool: None,
+            workers: None,
+            errors: ErrorRendering::default(),
+            limits: Limits::default(),
+            read_timeout: None,
+            request_timeout: Some(Duration::from_secs(30)),
+            write_timeout: None,
+            access_log: None,
+            metrics: None,
+            admin: None,
+        }
+    }
+
+    fn sockets(&self) -> impl Iterator<Item = &Socket> {
+        self.listeners
+            .iter()
+            .map(|listener| &listener.socket)
+            .chain(self.admin.as_ref().map(|(socket, _)| socket))
+    }
+
+    /// The TCP addresses actually bound, which tells which ports were picked for port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.sockets().filter_map(Socket::local_addr).collect()
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            stopping: Arc::clone(&self.stopping),
+            addrs: self.sockets().filter_map(Socket::wake_addr).collect(),
+        }
+    }
+
+    /// Accepts connections until stopped through a `StopHandle`
+    pub fn run(self) {
+        let Server {
+            listeners,
+            admin,
+            pool,
+            services,
+            stopping,
+        } = self;
+
+        if let Some((socket, admin_services)) = admin {
+            let stopping = Arc::clone(&stopping);
+            let admin_services = Arc::new(admin_services);
+            // dedicated threads, so that it stays reachable while the pool is saturated
+            thread::spawn(move || {
+                accept_loop(&socket, &stopping, |stream| {
+                    let services = Arc::clone(&admin_services);
+                    thread::spawn(move || serve(stream, None, &services));
+                });
+            });
+        }
+
+        match Reactor::new(listeners, pool, services) {
+            Ok(reactor) => reactor.run(&stopping),
+            Err(e) => log::error!("failed to start the event loop: {e}"),
+        }
+        log::info!("stopped accepting connections");
+    }
+}
+
+impl ServerBuilder {
+    /// Listens on `addr` too. Defaults to `127.0.0.1:4221` if never called.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.binds.push(Bind::Tcp(addr, None));
+        self
+    }
+
+    /// Listens for HTTPS on `addr` too. Listeners can share an acceptor, and with it the
+    /// certificates.
+    pub fn bind_tls(mut self, addr: SocketAddr, acceptor: Arc<TlsAcceptor>) -> Self {
+        self.binds.push(Bind::Tcp(addr, Some(acceptor)));
+        self
+    }
+
+    /// Listens on a Unix domain socket at `path` too, with the permissions `mode` if given. A
+    /// stale socket file at `path` is replaced.
+    pub fn bind_unix(mut self, path: impl Into<PathBuf>, mode: Option<u32>) -> Self {
+        let path = path.into();
+        self.binds.push(Bind::Unix { path, mode });
+        self
+    }
+
+    /// Listens on a socket inherited as the file descriptor `fd`, already bound and listening,
+    /// instead of binding a new one. The server takes ownership of `fd`.
+    pub fn bind_fd(mut self, fd: RawFd) -> Self {
+        self.binds.push(Bind::Fd(fd));
+        self
+    }
+
+    /// Defaults to the built-in endpoints, without any static mounts
+    pub fn router<F>(mut self, router: F) -> Self
+    where
+        F: Fn(Request) -> Response + Send + Sync + 'static,
+    {
+        self.router = Some(Box::new(router));
+        self
+    }
+
+    /// Defaults to admitting every request whose body is within the limits
+    pub fn admission<F>(mut self, admission: F) -> Self
+    where
+        F: Fn(&Request) -> Result<(), Response> + Send + Sync + 'static,
+    {
+        self.admission = Some(Box::new(admission));
+        self
+    }
+
+    /// Defaults to the labels of the built-in endpoints
+    pub fn route_label<F>(mut self, route_label: F) -> Self
+    where
+        F: Fn(&Request) -> Cow<'static, str> + Send + Sync + 'static,
+    {
+        self.route_label = Some(Box::new(route_label));
+        self
+    }
+
+    /// Runs `middleware` after those added before it
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.chain = self.chain.with(middleware);
+        self
+    }
+
+    /// The number of worker threads. Defaults to one less than the available parallelism, at
+    /// least 5.
+    pub fn workers(mut self, workers: u8) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+
+    /// Serves connections on `pool` rather than one of the builder's own
+    pub fn pool(mut self, pool: ThreadPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// How error responses get their bodies: those of the default router, and those rejecting
+    /// requests before they reach a router
+    pub fn errors(mut self, errors: ErrorRendering) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Closes connections once reading from them has stalled this long
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Answers requests that haven't arrived in full after this long with 408, closing the
+    /// connection. Defaults to 30 seconds. Unlike the read timeout, it doesn't apply to
+    /// connections idling between requests.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Records every request and connection in `metrics`
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Serves `chain` on `addr` from dedicated threads rather than the pool. Requests it passes
+    /// on get a 404.
+    pub fn admin_listener(mut self, addr: SocketAddr, chain: Chain) -> Self {
+        self.admin = Some((addr, chain));
+        self
+    }
+
+    /// Binds every listener
+    pub fn build(self) -> io::Result<Server> {
+        let mut binds = self.binds;
+        if binds.is_empty() {
+            binds.push(Bind::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, 4221)), None));
+        }
+        let listeners = binds
+            .into_iter()
+            .map(|bind| match bind {
+                Bind::Tcp(addr, tls) => {
+                    let socket = Socket::Tcp(TcpListener::bind(addr)?);
+                    let scheme = if tls.is_some() { "https" } else { "http" };
+                    log::info!("listening for {scheme} on {addr}");
+                    Ok(Listener { socket, tls })
+                }
+                Bind::Unix { path, mode } => {
+                    let socket = listener::bind_unix(&path, mode)?;
+                    log::info!("listening for http on {path:?}");
+                    Ok(Listener { socket, tls: None })
+                }
+                Bind::Fd(fd) => {
+                    let socket = listener::from_fd(fd)?;
+                    log::info!("listening for http on inherited fd {fd}");
+                    Ok(Listener { socket, tls: None })
+                }
+            })
+            .collect::<io::Result<_>>()?;
+
+        let admin = match self.admin {
+            Some((addr, chain)) => {
+                let listener = Socket::Tcp(TcpListener::bind(addr)?);
+                log::info!("admin listener on {addr}");
+                let services = Services {
+                    chain,
+                    router: Box::new(|_| client_error::not_found()),
+                    admission: Box::new(|_| Ok(())),
+                    route_label: Box::new(|_| Labels::UNPARSED.route),
+                    access_log: None,
+                    metrics: None,
+                    errors: self.errors.clone(),
+                    limits: self.limits,
+                    read_timeout: self.read_timeout,
+                    request_timeout: self.request_timeout,
+                    write_timeout: self.write_timeout,
+                };
+                Some((listener, services))
+            }
+            None => None,
+        };
+
+        let pool = self.pool.unwrap_or_else(|| match self.workers {
+            Some(size) => ThreadPool::new(size),
+            None => ThreadPool::auto(5),
+        });
+        let router = self.router.unwrap_or_else(|| {
+            let state = AppState {
+                errors: self.errors.clone(),
+                ..AppState::default()
+            };
+            Box::new(move |request| request.handle(&state))
+        });
+        // admits whatever is within the limits, like requests that don't ask are
+        let admission = self.admission.unwrap_or_else(|| Box::new(|_| Ok(())));
+        let route_label = self.route_label.unwrap_or_else(|| {
+            let state = AppState::default();
+            Box::new(move |request| request.route(&state))
+        });
+
+        Ok(Server {
+            listeners,
+            admin,
+            pool,
+            services: Arc::new(Services {
+                chain: self.chain,
+                router,
+                admission,
+                route_label,
+                access_log: self.access_log,
+                metrics: self.metrics,
+                errors: self.errors,
+                limits: self.limits,
+                read_timeout: self.read_timeout,
+                request_timeout: self.request_timeout,
+                write_timeout: self.write_timeout,
+            }),
+            stopping: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        // wake the event loop and the admin listener up, so that they notice
+        for addr in &self.addrs {
+            addr.wake();
+        }
+    }
+}
+
+fn accept_loop(socket: &Socket, stopping: &AtomicBool, mut dispatch: impl FnMut(Stream)) {
+    loop {
+        let stream = socket.accept();
+        if stopping.load(Ordering::Relaxed) {
+            break;
+        }
+        match stream {
+            Ok(stream) => dispatch(stream),
+            Err(e) => {
+                log::error!("connection failed: {e}");
+            }
+        }
+    }
+}
+
+/// Serves `stream`, over TLS if `tls` is set
+fn serve(stream: Stream, tls: Option<&TlsAcceptor>, services: &Services) {
+    let client = stream.peer();
+    if let Err(e) = stream.set_timeouts(services.read_timeout, services.write_timeout) {
+        log::error!("failed to set socket timeouts: {e}");
+    }
+    let result = match (stream, tls) {
+        (Stream::Tcp(stream), Some(acceptor)) => acceptor.accept(stream).map(|stream| {
+            handle_connection(Connection::shared(stream), client, true, services);
+        }),
+        (Stream::Tcp(stream), None) => Connection::tcp(stream)
+            .map(|connection| handle_connection(connection, client, false, services)),
+        (Stream::Unix(stream), _) => Connection::unix(stream)
+            .map(|connection| handle_connection(connection, client, false, services)),
+    };
+    if let Err(e) = result {
+        log::error!("failed to set up the connection: {e}");
+    }
+}
+
+/// Answers in the request's version, as far as the server speaks it, and marks whether the
+/// connection stays open
+fn negotiate_persistence(response: &mut Response, version: Version, keep_alive: bool) {
+    match version {
+        Version::Ver1_0 => response.set_version(Version::Ver1_0),
+        Version::Ver1_1 | Version::Ver2 => response.set_version(Version::Ver1_1),
+    }
+    if response.closing() {
+        return;
+    }
+    if !keep_alive {
+        response.add_header("Connection", "close");
+    } else if version == Version::Ver1_0 {
+        // persistence is opt-in for HTTP/1.0, so the client needs to hear it was granted
+        response.add_header("Connection", "keep-alive");
+    }
+}
+
+fn handle_connection<R: Read, W: Write>(
+    mut connection: Connection<R, W>,
+    client: Option<Peer>,
+    secure: bool,
+    services: &Services,
+) {
+    let _connection_guard = services.metrics.as_ref().map(|m| m.connection_opened());
+    let _connection_scope = trace::connection_scope(client);
+    log::info!("accepted new connection");
+
+    match h2::starts_with_preface(&mut connection.reader) {
+        Ok(true) => {
+            log::debug!("speaking HTTP/2");
+            let handler = StreamHandler { client, services };
+            return h2::serve(connection, None, &services.limits, handler);
+        }
+        Ok(false) => {}
+        Err(e) => {
+            log::debug!("connection closed before a request: {e}");
+            return;
+        }
+    }
+
+    loop {
+        let read = match connection
+            .read_head(&services.limits)
+            .and_then(|head| read_body(&mut connection, head, services))
+        {
+            // h2c is only for cleartext: over TLS, HTTP/2 is negotiated through ALPN
+            Ok(request) if !secure => match h2::upgrade_settings(&request) {
+                Some(settings) => {
+                    return upgrade_to_h2(connection, request, settings, client, services)
+                }
+                None => Ok(request),
+            },
+            Ok(request) => Ok(request),
+            Err(Some(err_response)) => Err(err_response),
+            Err(None) => break, // Stream has been closed
+        };
+        if !serve_request(&mut connection, read, client, services) {
+            break;
+        }
+    }
+}
+
+/// Answers a request that the reactor read off `client`, and hands the connection back unless it
+/// closes
+fn answer(
+    mut client: Client,
+    read: Result<Request, Response>,
+    services: &Arc<Services>,
+) -> Option<Client> {
+    let peer = client.peer;
+    let _connection_scope = trace::connection_scope(peer);
+    if let Err(e) = client.block(services) {
+        log::info!("failed to set up the connection: {e}");
+        return None;
+    }
+    let read = match read {
+        Ok(request) if !client.is_secure() => match h2::upgrade_settings(&request) {
+            Some(settings) => {
+                let services = Arc::clone(services);
+                spawn_dedicated(peer, move || {
+                    let connection = Connection::shared(client);
+                    upgrade_to_h2(connection, request, settings, peer, &services);
+                });
+                return None;
+            }
+            None => Ok(request),
+        },
+        read => read,
+    };
+    // tags the logs of everything up to sending the response
+    let (response, exchange, _request_scope) = dispatch(read, peer, services);
+    if response.takes_over() {
+        let services = Arc::clone(services);
+        let request_id = trace::request_id();
+        spawn_dedicated(peer, move || {
+            let _request_scope = request_id.as_deref().map(trace::request_scope);
+            let mut connection = Connection::shared(client);
+            send(&mut connection, response, exchange, peer, &services);
+        });
+        return None;
+    }
+    let mut connection = Connection::shared(client);
+    if !send(&mut connection, response, exchange, peer, services) {
+        return None;
+    }
+    let (mut client, unread) = connection.into_inner();
+    client.unread(unread);
+    Some(client)
+}
+
+/// Runs `serve` on a thread of its own, for a connection it keeps for as long as the client
+/// likes: a few such clients would otherwise leave the pool without a worker for anyone else
+fn spawn_dedicated(client: Option<Peer>, serve: impl FnOnce() + Send + 'static) {
+    let spawned = thread::Builder::new()
+        .name("connection".into())
+        .spawn(move || {
+            let _connection_scope = trace::connection_scope(client);
+            serve();
+        });
+    if let Err(e) = spawned {
+        log::error!("failed to spawn a thread for the connection: {e}");
+    }
+}
+
+/// Serves a connection that the reactor found to speak HTTP/2, until it closes
+fn serve_h2(mut client: Client, services: &Services) {
+    let peer = client.peer;
+    if let Err(e) = client.block(services) {
+        log::info!("failed to set up the connection: {e}");
+        return;
+    }
+    log::debug!("speaking HTTP/2");
+    let handler = StreamHandler { client: peer, services };
+    h2::serve(Connection::shared(client), None, &services.limits, handler);
+}
+
+/// Answers a request, or sends the response that rejected it. Returns whether the connection
+/// stays open for the next one.
+fn serve_request<R: Read, W: Write>(
+    connection: &mut Connection<R, W>,
+    read: Result<Request, Response>,
+    client: Option<Peer>,
+    services: &Services,
+) -> bool {
+    // tags the logs of everything up to sending the response
+    let (response, exchange, _request_scope) = dispatch(read, client, services);
+    send(connection, response, exchange, client, services)
+}
+
+/// Sends the response to a request, then hands the connection to its upgrade if it has one.
+/// Returns whether the connection stays open for the next request.
+fn send<R: Read, W: Write>(
+    connection: &mut Connection<R, W>,
+    mut response: Response,
+    exchange: Exchange,
+    client: Option<Peer>,
+    services: &Services,
+) -> bool {
+    // the connection is no longer HTTP once a protocol switch is sent
+    let upgrade = response.take_upgrade();
+    if let (Some((version, keep_alive)), None) = (exchange.persistence, &upgrade) {
+        negotiate_persistence(&mut response, version, keep_alive);
+    }
+
+    let close_sent = response.closing();
+    let status = response.status().code();
+
+    match connection.respond(response) {
+        Err(io_error) => {
+            if log::log_enabled!(Debug) {
+                log::debug!("failed to write response to stream: {io_error:?}");
+            } else {
+                log::info!("failed to write response to stream: {io_error}");
+            }
+        }
+        Ok(bytes_sent) => {
+            log::trace!("response sent");
+            record(exchange, client, status, bytes_sent, services);
+            if let Some(upgrade) = upgrade {
+                log::debug!("switched protocols");
+                upgrade(&mut connection.reader, &mut connection.writer);
+                return false;
+            }
+        }
+    }
+    !close_sent
+}
+
+/// Reads the body of a request, first telling a client that is waiting for it whether to send
+/// it at all
+fn read_body<R: Read, W: Write>(
+    connection: &mut Connection<R, W>,
+    head: Head,
+    services: &Services,
+) -> Result<Request, Option<Response>> {
+    match head.interim_response(&services.admission) {
+        Ok(Some(interim)) => {
+            if let Err(e) = connection.respond(interim) {
+                log::info!("failed to write 100 Continue: {e}");
+                return Err(None);
+            }
+        }
+        Ok(None) => {}
+        Err(response) => return Err(Some(response)),
+    }
+    connection.read_body(head)
+}
+
+/// Switches to HTTP/2, answering the request that asked for it on stream 1
+fn upgrade_to_h2<R: Read, W: Write>(
+    mut connection: Connection<R, W>,
+    request: Request,
+    settings: Vec<u8>,
+    client: Option<Peer>,
+    services: &Services,
+) {
+    if let Err(e) = connection.respond(informational::switching_protocols("h2c")) {
+        log::info!("failed to switch to HTTP/2: {e}");
+        return;
+    }
+    log::debug!("upgraded to HTTP/2");
+    let handler = StreamHandler { client, services };
+    let upgrade = Some((request, settings));
+    h2::serve(connection, upgrade, &services.limits, handler);
+}
+
+/// What is logged and measured about a request once its response is sent
+struct Exchange {
+    request_info: Option<RequestInfo>,
+    labels: Labels,
+    received: u64,
+    start: Instant,
+    /// The version of an HTTP/1 request and whether it asks to keep the connection open
+    persistence: Option<(Version, bool)>,
+}
+
+/// Handles a request, or passes on the response that rejected it while it was being read. Also
+/// returns a scope that tags logs with the request's ID for as long as it's kept.
+fn dispatch(
+    read: Result<Request, Response>,
+    client: Option<Peer>,
+    services: &Services,
+) -> (Response, Exchange, Option<trace::Scope>) {
+    let mut request = match read {
+        Ok(request) => request,
+        Err(mut err_response) => {
+            // found before the `Accept` header was known, if there is one
+            err_response.render_problem(None, &services.errors);
+            let exchange = Exchange {
+                request_info: None,
+                labels: Labels::UNPARSED,
+                received: 0,
+                start: Instant::now(),
+                persistence: None,
+            };
+            return (err_response, exchange, None);
+        }
+    };
+
+    let start = Instant::now();
+    if let Some(peer) = client {
+        request.set_peer(peer);
+    }
+    let id = trace::request_id_for(&request);
+    let scope = trace::request_scope(&id);
+    request.set_header("X-Request-Id", &id);
+    let exchange = Exchange {
+        request_info: Some(RequestInfo::from(&request)),
+        labels: Labels {
+            method: request.method().as_str(),
+            route: (services.route_label)(&request),
+        },
+        received: request.wire_size() as u64,
+        start,
+        persistence: match request.version() {
+            Version::Ver2 => None,
+            version => Some((*version, request.keep_alive())),
+        },
+    };
+    let accept = request.header("Accept").map(Box::<str>::from);
+    let mut response = handle_catching_panics(request, services);
+    response.render_problem(accept.as_deref(), &services.errors);
+    response.add_header("X-Request-Id", &id);
+    (response, exchange, Some(scope))
+}
+
+fn record(
+    exchange: Exchange,
+    client: Option<Peer>,
+    status: u16,
+    bytes_sent: BytesSent,
+    services: &Services,
+) {
+    let duration = exchange.start.elapsed();
+    if let Some(access_log) = &services.access_log {
+        access_log.log(&Record {
+            client,
+            request: exchange.request_info.as_ref(),
+            status,
+            body_bytes: bytes_sent.body,
+            duration,
+        });
+    }
+    if let Some(metrics) = &services.metrics {
+        let received = exchange.received;
+        metrics.record(&exchange.labels, status, duration, received, bytes_sent.total);
+    }
+}
+
+/// Serves the streams of an HTTP/2 connection as requests read off HTTP/1 ones are
+struct StreamHandler<'a> {
+    client: Option<Peer>,
+    services: &'a Services,
+}
+
+impl h2::Handler for StreamHandler<'_> {
+    type Context = Exchange;
+
+    fn handle(&mut self, request: Result<Request, Response>) -> (Response, Exchange) {
+        let (mut response, exchange, _request_scope) =
+            dispatch(request, self.client, self.services);
+        // a stream only ends when the connection closes, so it would keep every other one waiting
+        if response.take_stream().is_some() {
+            log::debug!("refusing to stream a body over HTTP/2");
+            response = server_error::http_version_not_supported();
+        }
+        (response, exchange)
+    }
+
+    fn sent(&mut self, exchange: Exchange, status: u16, bytes_sent: BytesSent) {
+        record(exchange, self.client, status, bytes_sent, self.services);
+    }
+}
+
+/// A panicking handler gets a 500 response rather than taking its worker down with it
+fn handle_catching_panics(request: Request, services: &Services) -> Response {
+    let context = request.summary();
+    let handle = || services.chain.handle(request, &*services.router);
+    panic::catch_unwind(AssertUnwindSafe(handle)).unwrap_or_else(|payload| {
+        log::error!(
+            "panicked while handling {context}: {}",
+            panic_message(payload.as_ref())
+        );
+        let mut response = server_error::generic();
+        // the handler may have left the connection in an unknown state
+        response.add_header("Connection", "close");
+        response
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(str) = payload.downcast_ref::<&str>() {
+        str
+    } else if let Some(string) = payload.downcast_ref::<String>() {
+        string
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dispatch, handle_catching_panics, panic_message, Router, Services};
+    use crate::{
+        http::{
+            error_page::ErrorRendering,
+            middleware::Chain,
+            request::{Limits, Request},
+            response::success,
+            Version,
+        },
+        trace,
+    };
+    use std::{collections::HashMap, panic};
+
+    fn services(router: Router) -> Services {
+        Services {
+            chain: Chain::default(),
+            router,
+            admission: Box::new(|_| Ok(())),
+            route_label: Box::new(|_| "/".into()),
+            access_log: None,
+            metrics: None,
+            errors: ErrorRendering::default(),
+            limits: Limits::default(),
+            read_timeout: None,
+            request_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    fn request(target: &str) -> Request {
+        request_with(target, HashMap::new())
+    }
+
+    fn request_with(target: &str, headers: HashMap<Box<str>, Box<str>>) -> Request {
+        Request::from_parts("GET", target, Version::Ver1_1, headers, Box::new([]), 0).unwrap()
+    }
+
+    #[test]
+    fn answers_panicking_handlers_with_a_server_error() {
+        let services = services(Box::new(|request| {
+            if request.target() == "/panic" {
+                panic!("handler bug");
+            }
+            success::plain_text("fine".to_string())
+        }));
+
+        let response = handle_catching_panics(request("/panic"), &services);
+        assert_eq!(response.status().code(), 500);
+        assert!(response.closing());
+
+        // the same services keep answering
+        let response = handle_catching_panics(request("/"), &services);
+        assert_eq!(response.status().code(), 200);
+        assert!(!response.closing());
+    }
+
+    #[test]
+    fn describes_panic_payloads() {
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "static");
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "formatted 1");
+        let payload = panic::catch_unwind(|| panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "<non-string panic payload>");
+    }
+
+    #[test]
+    fn propagates_request_ids() {
+        // the handler sees the ID both as a header and in the trace context
+        let services = services(Box::new(|request| {
+            let seen = format!("{:?} {:?}", request.header("X-Request-Id"), trace::request_id());
+            success::plain_text(seen)
+        }));
+
+        let headers = HashMap::from([("X-Request-Id".into(), "abc-123".into())]);
+        let (response, _, scope) = dispatch(Ok(request_with("/", headers)), None, &services);
+        assert_eq!(response.header("X-Request-Id"), Some("abc-123"));
+        assert_eq!(trace::request_id().as_deref(), Some("abc-123"));
+        drop(scope);
+        assert_eq!(trace::request_id(), None);
+        let (_, _, body) = response.into_parts();
+        assert_eq!(body, br#"Some("abc-123") Some("abc-123")"#);
+
+        let (response, _, _scope) = dispatch(Ok(request("/")), None, &services);
+        let generated = response.header("X-Request-Id").unwrap();
+        assert_eq!(generated.len(), 32, "{generated}");
+        assert_eq!(trace::request_id().as_deref(), Some(generated));
+    }
+}
@@ -0,0 +1,565 @@
+//! The event loop. A single thread waits on every listener and on every connection between
+//! requests, reading requests in as their bytes arrive, so that idle keep-alive connections cost
+//! no worker. A connection is handed to a worker of the pool once a whole request has arrived,
+//! and the worker hands it back once the request is answered.
+//!
+//! Connections that are taken over for as long as the client likes, such as HTTP/2 sessions,
+//! WebSockets and event streams, move to threads of their own instead, so that they never tie
+//! up the pool.
+
+use super::{answer, panic_message, serve_h2, spawn_dedicated, Listener, Services};
+use crate::{
+    http::{
+        connection::Peer,
+        h2,
+        request::{Head, Parser, Request},
+        response::{client_error, Response},
+    },
+    listener::Stream,
+    metrics::ConnectionGuard,
+    thread_pool::ThreadPool,
+    tls::{TlsAcceptor, TlsStream},
+    trace,
+};
+use mio::{unix::SourceFd, Events, Interest, Poll, Token, Waker};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    os::fd::{AsRawFd, RawFd},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Wakes the reactor up when a worker hands a connection back
+const WAKER: Token = Token(usize::MAX);
+
+/// How much is read off a connection at a time
+const READ_CHUNK: usize = 16 * 1024;
+
+pub(super) struct Reactor {
+    poll: Poll,
+    /// Registered with their index as the token
+    listeners: Vec<Listener>,
+    clients: HashMap<Token, Client>,
+    next_token: usize,
+    handed_back: Receiver<Client>,
+    hand_back: HandBack,
+    pool: ThreadPool,
+    services: Arc<Services>,
+}
+
+/// What a worker is handed along with a connection
+enum Job {
+    Answer(Result<Request, Response>),
+    Http2,
+}
+
+enum Step {
+    /// For more bytes to arrive
+    Wait,
+    Close,
+    HandOff(Job),
+}
+
+/// Gives connections back to the reactor once a worker is done with them
+#[derive(Clone)]
+struct HandBack {
+    sender: Sender<Client>,
+    waker: Arc<Waker>,
+}
+
+impl HandBack {
+    fn send(&self, client: Client) {
+        // fails once the reactor has stopped, which closes the connection
+        if self.sender.send(client).is_ok() {
+            if let Err(e) = self.waker.wake() {
+                log::error!("failed to wake the event loop: {e}");
+            }
+        }
+    }
+}
+
+impl Reactor {
+    pub fn new(
+        listeners: Vec<Listener>,
+        pool: ThreadPool,
+        services: Arc<Services>,
+    ) -> io::Result<Reactor> {
+        let poll = Poll::new()?;
+        for (index, listener) in listeners.iter().enumerate() {
+            listener.socket.set_nonblocking(true)?;
+            let fd = listener.socket.as_raw_fd();
+            poll.registry()
+                .register(&mut SourceFd(&fd), Token(index), Interest::READABLE)?;
+        }
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (sender, handed_back) = mpsc::channel();
+        Ok(Reactor {
+            poll,
+            next_token: listeners.len(),
+            listeners,
+            clients: HashMap::new(),
+            handed_back,
+            hand_back: HandBack { sender, waker },
+            pool,
+            services,
+        })
+    }
+
+    /// Runs until `stopping` is set and a listener is woken up to notice it. Connections still
+    /// waiting for a request are closed then.
+    pub fn run(mut self, stopping: &AtomicBool) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            let timeout = self
+                .clients
+                .values()
+                .filter_map(|client| client.deadline(&self.services))
+                .min()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log::error!("failed to wait for events: {e}");
+                break;
+            }
+            if stopping.load(Ordering::Relaxed) {
+                break;
+            }
+            for event in &events {
+                match event.token() {
+                    WAKER => {}
+                    Token(index) if index < self.listeners.len() => self.accept(index),
+                    token => self.advance(token),
+                }
+            }
+            while let Ok(client) = self.handed_back.try_recv() {
+                self.add(client);
+            }
+            self.expire();
+        }
+    }
+
+    fn accept(&mut self, index: usize) {
+        loop {
+            let listener = &self.listeners[index];
+            let stream = match listener.socket.accept() {
+                Ok(stream) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::error!("connection failed: {e}");
+                    return;
+                }
+            };
+            let tls = listener.tls.clone();
+            match Client::new(stream, tls.as_deref(), &self.services) {
+                Ok(client) => self.add(client),
+                Err(e) => log::error!("failed to set up the connection: {e}"),
+            }
+        }
+    }
+
+    /// Starts waiting on `client`, whether it's new or handed back by a worker
+    fn add(&mut self, mut client: Client) {
+        let _connection_scope = trace::connection_scope(client.peer);
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        let registered = client.transport.set_nonblocking(true).and_then(|()| {
+            let interest = Interest::READABLE | Interest::WRITABLE;
+            self.poll
+                .registry()
+                .register(&mut SourceFd(&client.fd()), token, interest)
+        });
+        if let Err(e) = registered {
+            log::error!("failed to wait on the connection: {e}");
+            return;
+        }
+        let now = Instant::now();
+        client.last_read = now;
+        client.started = (!client.is_idle()).then_some(now);
+        self.clients.insert(token, client);
+        // bytes that arrived along with the last request are not announced again
+        self.advance(token);
+    }
+
+    fn advance(&mut self, token: Token) {
+        let Some(client) = self.clients.get_mut(&token) else {
+            return;
+        };
+        let _connection_scope = trace::connection_scope(client.peer);
+        match client.advance(&self.services) {
+            Step::Wait => {}
+            Step::Close => drop(self.remove(token)),
+            Step::HandOff(job) => {
+                if let Some(client) = self.remove(token) {
+                    self.hand_off(client, job);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, token: Token) -> Option<Client> {
+        let client = self.clients.remove(&token)?;
+        if let Err(e) = self.poll.registry().deregister(&mut SourceFd(&client.fd())) {
+            log::warn!("failed to stop waiting on the connection: {e}");
+        }
+        Some(client)
+    }
+
+    fn hand_off(&self, mut client: Client, job: Job) {
+        client.served = true;
+        let services = Arc::clone(&self.services);
+        match job {
+            Job::Answer(read) => {
+                let hand_back = self.hand_back.clone();
+                self.pool.execute(move || {
+                    if let Some(client) = answer(client, read, &services) {
+                        hand_back.send(client);
+                    }
+                });
+            }
+            Job::Http2 => {
+                let peer = client.peer;
+                spawn_dedicated(peer, move || serve_h2(client, &services));
+            }
+        }
+    }
+
+    /// Gives up on connections that stalled, or whose request is taking too long
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                let deadline = client.deadline(&self.services);
+                deadline.is_some_and(|deadline| deadline <= now)
+            })
+            .map(|(&token, _)| token)
+            .collect();
+        for token in expired {
+            let Some(client) = self.remove(token) else {
+                continue;
+            };
+            let _connection_scope = trace::connection_scope(client.peer);
+            // an idle keep-alive connection timing out is not an error
+            if client.is_idle() {
+                log::debug!("closing idle connection");
+                continue;
+            }
+            // there is no way to answer before the handshake is done
+            if client.is_handshaking() {
+                log::info!("dropping connection: TLS handshake timed out");
+                continue;
+            }
+            let mut response = client_error::request_timeout();
+            response.add_header("Connection", "close");
+            self.hand_off(client, Job::Answer(Err(response)));
+        }
+    }
+}
+
+/// A connection, along with what has been read off it
+pub(super) struct Client {
+    transport: Transport,
+    pub(super) peer: Option<Peer>,
+    /// Read but not parsed yet: the part of a request that has arrived, and whatever follows it
+    buf: Vec<u8>,
+    parser: Parser,
+    /// The head of a request whose body is still arriving
+    head: Option<Head>,
+    /// Written as soon as the connection takes it, e.g. `100 Continue`
+    pending: Vec<u8>,
+    /// Whether a request was answered, after which the connection can't switch to HTTP/2
+    served: bool,
+    last_read: Instant,
+    /// When the request that is partly read, or the TLS handshake, began arriving
+    started: Option<Instant>,
+    _connection_guard: Option<ConnectionGuard>,
+}
+
+enum Transport {
+    Plain(Stream),
+    Tls(Box<TlsStream>),
+}
+
+impl Client {
+    fn new(stream: Stream, tls: Option<&TlsAcceptor>, services: &Services) -> io::Result<Client> {
+        let peer = stream.peer();
+        let _connection_scope = trace::connection_scope(peer);
+        log::info!("accepted new connection");
+        let transport = match (stream, tls) {
+            (Stream::Tcp(stream), Some(acceptor)) => {
+                Transport::Tls(Box::new(acceptor.accept(stream)?))
+            }
+            (stream, _) => Transport::Plain(stream),
+        };
+        Ok(Client {
+            transport,
+            peer,
+            buf: Vec::new(),
+            parser: Parser::default(),
+            head: None,
+            pending: Vec::new(),
+            served: false,
+            last_read: Instant::now(),
+            started: None,
+            _connection_guard: services.metrics.as_ref().map(|m| m.connection_opened()),
+        })
+    }
+
+    fn fd(&self) -> RawFd {
+        match &self.transport {
+            Transport::Plain(stream) => stream.as_raw_fd(),
+            Transport::Tls(tls) => tls.sock.as_raw_fd(),
+        }
+    }
+
+    /// Whether no part of a request has arrived
+    fn is_idle(&self) -> bool {
+        self.buf.is_empty() && self.head.is_none() && !self.is_handshaking()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        matches!(&self.transport, Transport::Tls(tls) if tls.conn.is_handshaking())
+    }
+
+    /// When the connection is given up on: once reading from it stalls for the read timeout,
+    /// and once a request takes longer than the request timeout to arrive in full
+    fn deadline(&self, services: &Services) -> Option<Instant> {
+        let stalled = services.read_timeout.map(|timeout| self.last_read + timeout);
+        let started = self.started.zip(services.request_timeout);
+        let slow = started.map(|(started, timeout)| started + timeout);
+        stalled.into_iter().chain(slow).min()
+    }
+
+    pub(super) fn is_secure(&self) -> bool {
+        matches!(self.transport, Transport::Tls(_))
+    }
+
+    /// Reads whatever has arrived, until it makes up a request or nothing more is there
+    fn advance(&mut self, services: &Services) -> Step {
+        if let Err(e) = self.flush() {
+            log::debug!("failed to write to the connection: {e}");
+            return Step::Close;
+        }
+        let mut chunk = [0; READ_CHUNK];
+        loop {
+            match self.parse(services) {
+                Step::Wait => {}
+                step => return step,
+            }
+            match self.transport.read(&mut chunk) {
+                Ok(0) => {
+                    if !self.is_idle() {
+                        log::debug!("connection closed partway through a request");
+                    }
+                    return Step::Close;
+                }
+                Ok(read) => {
+                    self.buf.extend_from_slice(&chunk[..read]);
+                    let now = Instant::now();
+                    self.last_read = now;
+                    self.started.get_or_insert(now);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // e.g. the TLS handshake, which may not have been written in full
+                    return match self.flush() {
+                        Ok(()) => Step::Wait,
+                        Err(e) => {
+                            log::debug!("failed to write to the connection: {e}");
+                            Step::Close
+                        }
+                    };
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                // e.g. a failed TLS handshake, which leaves nobody to answer
+                Err(e) => {
+                    log::info!("dropping connection: {e}");
+                    return Step::Close;
+                }
+            }
+        }
+    }
+
+    /// Makes as much of a request out of what has arrived as it can
+    fn parse(&mut self, services: &Services) -> Step {
+        if self.head.is_none() {
+            if self.buf.is_empty() {
+                return Step::Wait;
+            }
+            if !self.served {
+                let http2 = match &self.transport {
+                    // negotiated during the handshake, which is over once any request arrives
+                    Transport::Tls(tls) => Some(tls.conn.alpn_protocol() == Some(h2::ALPN)),
+                    Transport::Plain(_) => h2::sniff_preface(&self.buf),
+                };
+                match http2 {
+                    Some(true) => return Step::HandOff(Job::Http2),
+                    Some(false) => {}
+                    None => return Step::Wait,
+                }
+            }
+            // a panic here would take down the event loop, and with it every connection
+            let parse = || self.parser.parse_head(&self.buf, &services.limits);
+            let parsed = panic::catch_unwind(AssertUnwindSafe(parse)).unwrap_or_else(|payload| {
+                log::error!("panicked while parsing a head: {}", panic_message(payload.as_ref()));
+                let mut response = client_error::bad_request();
+                response.add_header("Connection", "close");
+                Err(Some(response))
+            });
+            let (head, len) = match parsed {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => return Step::Wait,
+                Err(Some(response)) => {
+                    // as the blocking path does, so that the malformed head isn't parsed again
+                    self.buf.clear();
+                    return Step::HandOff(Job::Answer(Err(response)));
+                }
+                Err(None) => return Step::Close,
+            };
+            self.buf.drain(..len);
+            match head.interim_response(&services.admission) {
+                Ok(Some(interim)) => {
+                    interim
+                        .write_to(&mut self.pending)
+                        .expect("writing to memory is infallible");
+                    if let Err(e) = self.flush() {
+                        log::debug!("failed to write 100 Continue: {e}");
+                        return Step::Close;
+                    }
+                }
+                Ok(None) => {}
+                Err(response) => return Step::HandOff(Job::Answer(Err(response))),
+            }
+            self.head = Some(head);
+        }
+
+        match self.head.take() {
+            Some(head) if self.buf.len() >= head.body_len() => {
+                let body = self.buf.drain(..head.body_len()).collect();
+                Step::HandOff(Job::Answer(Ok(head.into_request(body))))
+            }
+            head => {
+                self.head = head;
+                Step::Wait
+            }
+        }
+    }
+
+    /// Writes what is pending, as far as the connection takes it without blocking
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.transport {
+            Transport::Plain(stream) => {
+                while !self.pending.is_empty() {
+                    match stream.write(&self.pending) {
+                        Ok(written) => drop(self.pending.drain(..written)),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Transport::Tls(tls) => {
+                if !self.pending.is_empty() {
+                    let written = tls.conn.writer().write(&self.pending)?;
+                    self.pending.drain(..written);
+                }
+                while tls.conn.wants_write() {
+                    match tls.conn.write_tls(&mut tls.sock) {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares the connection for a worker, which blocks on it
+    pub(super) fn block(&mut self, services: &Services) -> io::Result<()> {
+        self.transport.set_nonblocking(false)?;
+        let (read, write) = (services.read_timeout, services.write_timeout);
+        match &self.transport {
+            Transport::Plain(stream) => stream.set_timeouts(read, write)?,
+            Transport::Tls(tls) => {
+                tls.sock.set_read_timeout(read)?;
+                tls.sock.set_write_timeout(write)?;
+            }
+        }
+        self.flush()?;
+        self.transport.flush()
+    }
+
+    /// Puts back bytes that were read off the connection but not used
+    pub(super) fn unread(&mut self, mut bytes: Vec<u8>) {
+        bytes.append(&mut self.buf);
+        self.buf = bytes;
+    }
+}
+
+/// What was read ahead comes first
+impl Read for Client {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            return self.transport.read(buf);
+        }
+        let len = self.buf.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for Client {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transport.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+impl Transport {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.set_nonblocking(nonblocking),
+            Transport::Tls(tls) => tls.sock.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Tls(tls) => tls.flush(),
+        }
+    }
+}
@@ -1,11 +1,37 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::thread;
 
 pub struct ThreadPool {
     #[allow(dead_code)]
     workers: Vec<Worker>,
     sender: Sender<Job>,
+    stats: Arc<PoolStats>,
+}
+
+/// Live counters describing how loaded a `ThreadPool` is
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    size: usize,
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+}
+
+impl PoolStats {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Jobs waiting for a free worker
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Workers currently executing a job
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
 }
 
 impl ThreadPool {
@@ -15,8 +41,13 @@ impl ThreadPool {
     {
         let job = Box::new(f);
 
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
         self.sender.send(job).unwrap();
     }
+
+    pub fn stats(&self) -> Arc<PoolStats> {
+        Arc::clone(&self.stats)
+    }
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
@@ -27,28 +58,116 @@ impl ThreadPool {
             .map_or(1, usize::from)
             .try_into()
             .unwrap_or(1);
-        let pool_size = (available - 1).max(min_size);
+        ThreadPool::new((available - 1).max(min_size))
+    }
 
+    pub fn new(pool_size: u8) -> ThreadPool {
         let mut workers = Vec::with_capacity(pool_size as usize);
         let (sender, receiver) = mpsc::channel();
         let receiver = Arc::new(Mutex::new(receiver));
+        let stats = Arc::new(PoolStats {
+            size: pool_size.into(),
+            ..Default::default()
+        });
 
         for id in 0..pool_size {
-            workers.push(new_worker(id, Arc::clone(&receiver)));
+            workers.push(new_worker(id, Arc::clone(&receiver), Arc::clone(&stats)));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool {
+            workers,
+            sender,
+            stats,
+        }
     }
 }
 
 type Worker = thread::JoinHandle<()>;
 
-fn new_worker(id: u8, receiver: Arc<Mutex<Receiver<Job>>>) -> Worker {
-    let thread = thread::spawn(move || loop {
-        let job = receiver.lock().unwrap().recv().unwrap();
-        log::info!("worker {id} got a job; executing");
-        job();
+fn new_worker(id: u8, receiver: Arc<Mutex<Receiver<Job>>>, stats: Arc<PoolStats>) -> Worker {
+    let thread = thread::Builder::new().name(format!("worker-{id}"));
+    let thread = thread.spawn(move || {
+        let mut sentinel = Sentinel {
+            id,
+            receiver: Arc::clone(&receiver),
+            stats: Arc::clone(&stats),
+            busy: false,
+        };
+        loop {
+            // The lock is only held while waiting, so a panicking job can't poison it. Should it
+            // be poisoned anyway, the receiver inside is still perfectly usable.
+            let message = receiver
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .recv();
+            let Ok(job) = message else {
+                log::debug!("worker {id} shutting down: the pool was dropped");
+                break;
+            };
+            log::info!("worker {id} got a job; executing");
+            stats.queued.fetch_sub(1, Ordering::Relaxed);
+            stats.busy.fetch_add(1, Ordering::Relaxed);
+            sentinel.busy = true;
+            job();
+            sentinel.busy = false;
+            stats.busy.fetch_sub(1, Ordering::Relaxed);
+        }
     });
 
-    thread
+    thread.expect("failed to spawn a worker thread")
+}
+
+/// Replaces its worker if the worker thread dies from a panic
+struct Sentinel {
+    id: u8,
+    receiver: Arc<Mutex<Receiver<Job>>>,
+    stats: Arc<PoolStats>,
+    /// Whether the worker died in the middle of a job
+    busy: bool,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            log::error!("worker {} panicked; respawning it", self.id);
+            if self.busy {
+                self.stats.busy.fetch_sub(1, Ordering::Relaxed);
+            }
+            new_worker(self.id, Arc::clone(&self.receiver), Arc::clone(&self.stats));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadPool;
+    use std::{
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn respawns_workers_that_panic() {
+        let pool = ThreadPool::new(1);
+        let stats = pool.stats();
+        pool.execute(|| panic!("job bug"));
+
+        // with the only worker gone, this job runs on its replacement
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || {
+            let name = thread::current().name().map(str::to_string);
+            sender.send(name).unwrap();
+        });
+        let name = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(name.as_deref(), Some("worker-0"));
+
+        assert_eq!(stats.size(), 1);
+        assert_eq!(stats.queued(), 0);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stats.busy() > 0 {
+            assert!(Instant::now() < deadline, "the panicked job is still counted as busy");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
 }
@@ -0,0 +1,217 @@
+use crate::http::h2;
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection, StreamOwned,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    io,
+    net::TcpStream,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+};
+use thiserror::Error;
+
+/// Protocols offered through ALPN, most preferred first
+const ALPN_PROTOCOLS: [&[u8]; 2] = [h2::ALPN, b"http/1.1"];
+
+/// A certificate chain and its private key, both PEM files
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Certificate {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// The SNI names to present this certificate for. The first certificate is also presented
+    /// to clients asking for no name, or for a name no certificate has.
+    #[serde(default)]
+    pub hostnames: Vec<Box<str>>,
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("no certificates configured")]
+    NoCertificates,
+    #[error("failed to read {path:?}: {source}")]
+    Pem {
+        path: PathBuf,
+        source: rustls::pki_types::pem::Error,
+    },
+    #[error("invalid certificate or key in {path:?}: {source}")]
+    Rustls { path: PathBuf, source: rustls::Error },
+}
+
+/// Wraps accepted connections in TLS, reloading the certificates from disk on SIGHUP
+pub struct TlsAcceptor {
+    certificates: Vec<Certificate>,
+    config: RwLock<Arc<ServerConfig>>,
+    /// Set by SIGHUP, so that the certificates are reloaded before the next handshake
+    reload: Arc<AtomicBool>,
+}
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+impl TlsAcceptor {
+    pub fn new(certificates: Vec<Certificate>) -> Result<TlsAcceptor, TlsError> {
+        let config = server_config(&certificates)?;
+        let reload = Arc::new(AtomicBool::new(false));
+        if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload)) {
+            log::error!("failed to register certificate reloading on SIGHUP: {e}");
+        }
+        Ok(TlsAcceptor {
+            certificates,
+            config: RwLock::new(config),
+            reload,
+        })
+    }
+
+    /// Starts a TLS session on `stream`. The handshake itself happens on the first read or write.
+    pub fn accept(&self, stream: TcpStream) -> io::Result<TlsStream> {
+        if self.reload.swap(false, Ordering::Relaxed) {
+            match server_config(&self.certificates) {
+                Ok(config) => {
+                    log::info!("reloaded TLS certificates");
+                    *self.config.write().unwrap_or_else(PoisonError::into_inner) = config;
+                }
+                Err(e) => log::error!("failed to reload TLS certificates, keeping the old ones: {e}"),
+            }
+        }
+
+        let config = Arc::clone(&self.config.read().unwrap_or_else(PoisonError::into_inner));
+        let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+/// Loads every certificate and key, to report problems with them without starting a listener
+pub fn check(certificates: &[Certificate]) -> Result<(), TlsError> {
+    server_config(certificates).map(drop)
+}
+
+fn server_config(certificates: &[Certificate]) -> Result<Arc<ServerConfig>, TlsError> {
+    let provider = Arc::new(ring::default_provider());
+    let resolver = SniResolver::load(certificates, &provider)?;
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+    Ok(Arc::new(config))
+}
+
+/// Picks a certificate by the name the client asked for through SNI
+#[derive(Debug)]
+struct SniResolver {
+    by_name: HashMap<Box<str>, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl SniResolver {
+    fn load(certificates: &[Certificate], provider: &CryptoProvider) -> Result<Self, TlsError> {
+        let mut by_name = HashMap::new();
+        let mut default = None;
+        for certificate in certificates {
+            let key = Arc::new(load_certified_key(certificate, provider)?);
+            for hostname in &certificate.hostnames {
+                by_name.insert(hostname.to_ascii_lowercase().into(), Arc::clone(&key));
+            }
+            default.get_or_insert(key);
+        }
+        Ok(SniResolver {
+            by_name,
+            default: default.ok_or(TlsError::NoCertificates)?,
+        })
+    }
+
+    /// The certificate for `server_name`, or the default one if there is none for it
+    fn select(&self, server_name: Option<&str>) -> &Arc<CertifiedKey> {
+        server_name
+            .and_then(|name| self.by_name.get(name.to_ascii_lowercase().as_str()))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(self.select(client_hello.server_name())))
+    }
+}
+
+fn load_certified_key(
+    certificate: &Certificate,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, TlsError> {
+    let pem_error = |path: &PathBuf| {
+        let path = path.clone();
+        move |source| TlsError::Pem { path, source }
+    };
+    let chain = CertificateDer::pem_file_iter(&certificate.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(pem_error(&certificate.cert))?;
+    let key = PrivateKeyDer::from_pem_file(&certificate.key).map_err(pem_error(&certificate.key))?;
+    CertifiedKey::from_der(chain, key, provider).map_err(|source| TlsError::Rustls {
+        path: certificate.key.clone(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Certificate, SniResolver, TlsError};
+    use rustls::crypto::ring;
+    use std::{fs, path::PathBuf};
+
+    /// Writes a self-signed certificate for `hostname` and its key to PEM files
+    fn self_signed(hostname: &str, hostnames: &[&str]) -> (Certificate, Vec<u8>) {
+        let generated = rcgen::generate_simple_self_signed([hostname.to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("tls-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |extension: &str, pem: String| {
+            let path: PathBuf = dir.join(format!("{hostname}.{extension}"));
+            fs::write(&path, pem).unwrap();
+            path
+        };
+        let certificate = Certificate {
+            cert: write("crt", generated.cert.pem()),
+            key: write("key", generated.key_pair.serialize_pem()),
+            hostnames: hostnames.iter().map(|&name| name.into()).collect(),
+        };
+        (certificate, generated.cert.der().to_vec())
+    }
+
+    #[test]
+    fn selects_certificates_by_server_name() {
+        let (a, a_der) = self_signed("a.test", &["a.test", "www.a.test"]);
+        let (b, b_der) = self_signed("b.test", &["B.test"]);
+        let resolver = SniResolver::load(&[a, b], &ring::default_provider()).unwrap();
+        let presented = |name| resolver.select(name).cert[0].to_vec();
+
+        assert_eq!(presented(Some("a.test")), a_der);
+        assert_eq!(presented(Some("www.a.test")), a_der);
+        assert_eq!(presented(Some("b.test")), b_der);
+        assert_eq!(presented(Some("B.TEST")), b_der);
+        // the first certificate is the fallback
+        assert_eq!(presented(Some("c.test")), a_der);
+        assert_eq!(presented(None), a_der);
+    }
+
+    #[test]
+    fn refuses_to_start_without_certificates_or_with_mismatched_keys() {
+        let provider = ring::default_provider();
+        let none = SniResolver::load(&[], &provider);
+        assert!(matches!(none, Err(TlsError::NoCertificates)));
+
+        let (a, _) = self_signed("mismatch-a.test", &[]);
+        let (b, _) = self_signed("mismatch-b.test", &[]);
+        let mismatched = Certificate { key: b.key, ..a };
+        let loaded = SniResolver::load(&[mismatched], &provider);
+        assert!(matches!(loaded, Err(TlsError::Rustls { .. })));
+    }
+}
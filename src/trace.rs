@@ -0,0 +1,201 @@
+use crate::http::{connection::Peer, request::Request};
+use std::{
+    cell::RefCell,
+    fmt::Write,
+    hash::{BuildHasher, Hasher, RandomState},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+/// Longer client-supplied IDs are replaced rather than trusted
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::default();
+}
+
+/// What the current thread is working on, attached to every log record it emits
+#[derive(Default)]
+struct Context {
+    client: Option<Peer>,
+    request_id: Option<Box<str>>,
+}
+
+/// Restores the previous context when dropped
+#[must_use]
+pub struct Scope {
+    previous: Context,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = std::mem::take(&mut self.previous);
+        CONTEXT.with_borrow_mut(|context| *context = previous);
+    }
+}
+
+/// Tags this thread's log records with the client being served, until the scope is dropped
+pub fn connection_scope(client: Option<Peer>) -> Scope {
+    let context = Context {
+        client,
+        request_id: None,
+    };
+    Scope {
+        previous: CONTEXT.replace(context),
+    }
+}
+
+/// Tags this thread's log records with `request_id` too, until the scope is dropped
+pub fn request_scope(request_id: &str) -> Scope {
+    let context = Context {
+        client: CONTEXT.with_borrow(|context| context.client),
+        request_id: Some(request_id.into()),
+    };
+    Scope {
+        previous: CONTEXT.replace(context),
+    }
+}
+
+/// The ID this thread's log records are tagged with, to go on tagging them from another thread
+pub fn request_id() -> Option<Box<str>> {
+    CONTEXT.with_borrow(|context| context.request_id.clone())
+}
+
+/// e.g. ` [worker-2 127.0.0.1:51234 req=4bf92f3577b34da6a3ce929d0e0e4736]`, or nothing if the
+/// thread is unnamed and has no context
+pub fn log_context() -> String {
+    let mut out = String::new();
+    if let Some(name) = thread::current().name() {
+        out.push_str(name);
+    }
+    CONTEXT.with_borrow(|context| {
+        if let Some(client) = context.client {
+            let _ = write!(out, " {client}");
+        }
+        if let Some(request_id) = &context.request_id {
+            let _ = write!(out, " req={request_id}");
+        }
+    });
+    match out.trim_start() {
+        "" => String::new(),
+        trimmed => format!(" [{trimmed}]"),
+    }
+}
+
+/// The client's `X-Request-Id`, else the trace ID of a W3C `traceparent`, else a new ID
+pub fn request_id_for(request: &Request) -> Box<str> {
+    let from_header = request.header("X-Request-Id").filter(|id| {
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LEN
+            && id.bytes().all(|b| b.is_ascii_graphic())
+    });
+    if let Some(id) = from_header {
+        return id.into();
+    }
+
+    request
+        .header("traceparent")
+        .and_then(trace_id)
+        .map_or_else(generate_id, Into::into)
+}
+
+/// Parses the trace ID out of `version-traceid-parentid-flags`
+fn trace_id(traceparent: &str) -> Option<&str> {
+    let mut parts = traceparent.trim().split('-');
+    let _version = parts.next()?;
+    let trace_id = parts.next()?;
+    let is_valid = trace_id.len() == 32
+        && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+        && trace_id.bytes().any(|b| b != b'0');
+    is_valid.then_some(trace_id)
+}
+
+/// 32 random hex digits, the same shape as a trace ID
+fn generate_id() -> Box<str> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    // `RandomState` is randomly seeded, so hashing a counter gives unpredictable output
+    let random = |salt: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(count ^ salt);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", random(0), random(u64::MAX)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connection_scope, log_context, request_id, request_id_for, request_scope};
+    use crate::http::{connection::Peer, request::Request, Version};
+    use std::{collections::HashMap, thread};
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let headers: HashMap<Box<str>, Box<str>> =
+            headers.iter().map(|&(key, value)| (key.into(), value.into())).collect();
+        Request::from_parts("GET", "/", Version::Ver1_1, headers, Box::new([]), 0).unwrap()
+    }
+
+    fn is_generated(id: &str) -> bool {
+        id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn takes_request_ids_from_the_client() {
+        let id = request_id_for(&request(&[("X-Request-Id", "abc-123")]));
+        assert_eq!(&*id, "abc-123");
+        let id = request_id_for(&request(&[("traceparent", TRACEPARENT)]));
+        assert_eq!(&*id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        let both = [("X-Request-Id", "abc-123"), ("traceparent", TRACEPARENT)];
+        assert_eq!(&*request_id_for(&request(&both)), "abc-123");
+    }
+
+    #[test]
+    fn generates_ids_in_place_of_unusable_ones() {
+        let too_long = "a".repeat(129);
+        let all_zeros = format!("00-{}-00f067aa0ba902b7-01", "0".repeat(32));
+        for headers in [
+            &[][..],
+            &[("X-Request-Id", "")],
+            &[("X-Request-Id", "a b")],
+            &[("X-Request-Id", too_long.as_str())],
+            &[("traceparent", "00-4bf92f35-00f067aa0ba902b7-01")],
+            &[("traceparent", all_zeros.as_str())],
+        ] {
+            let id = request_id_for(&request(headers));
+            assert!(is_generated(&id), "{headers:?} gave {id}");
+        }
+        assert_ne!(request_id_for(&request(&[])), request_id_for(&request(&[])));
+    }
+
+    #[test]
+    fn tags_logs_until_scopes_end() {
+        let thread = thread::Builder::new().name("worker-7".into());
+        let handle = thread.spawn(|| {
+            assert_eq!(log_context(), " [worker-7]");
+            let client = Peer::Tcp(([127, 0, 0, 1], 4000).into());
+            let _connection = connection_scope(Some(client));
+            {
+                let _request = request_scope("req-1");
+                assert_eq!(log_context(), " [worker-7 127.0.0.1:4000 req=req-1]");
+                assert_eq!(request_id().as_deref(), Some("req-1"));
+            }
+            assert_eq!(log_context(), " [worker-7 127.0.0.1:4000]");
+            assert_eq!(request_id(), None);
+        });
+        handle.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn carries_request_ids_to_other_threads() {
+        let _request = request_scope("req-2");
+        let id = request_id();
+        let handle = thread::spawn(move || {
+            assert_eq!(log_context(), "");
+            let _request = id.as_deref().map(request_scope);
+            log_context()
+        });
+        assert_eq!(handle.join().unwrap(), " [req=req-2]");
+    }
+}